@@ -0,0 +1,145 @@
+//! Running the `passwordcmd` of a .netrc entry.
+
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zeroize::Zeroizing;
+
+/// Run a command with the shell, returning its trimmed output.
+///
+/// The command is killed, along with anything it started, if it doesn't finish and close
+/// its output within `timeout`. If it fails, the error includes what it printed to stderr.
+pub fn run(command: &str, timeout: Duration) -> Result<Zeroizing<String>, String> {
+    // Cargo talks to credential providers over stdin and stdout, so the command must not
+    // use them.
+    let mut child = shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run `{command}`: {e}"))?;
+
+    // Read the output while waiting, so that the command doesn't block on a full pipe.
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            pipe.read_to_end(&mut output).map(|_| output)
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+    // The readers only finish once everything holding the pipes has exited, which includes
+    // anything the command left running in the background, so they count towards the
+    // timeout too.
+    let deadline = Instant::now() + timeout;
+    let mut exited = None;
+    let status = loop {
+        if exited.is_none() {
+            exited = match child.try_wait() {
+                Ok(exited) => exited,
+                Err(e) => {
+                    kill(&mut child);
+                    return Err(format!("unable to wait for `{command}`: {e}"));
+                }
+            };
+        }
+        match exited {
+            Some(status) if stdout.is_finished() && stderr.is_finished() => break status,
+            _ if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            _ => {
+                kill(&mut child);
+                return Err(format!("`{command}` didn't finish within {timeout:?}"));
+            }
+        }
+    };
+
+    let stdout = Zeroizing::new(stdout.join().unwrap().unwrap_or_default());
+    let stderr = stderr.join().unwrap().unwrap_or_default();
+    if !status.success() {
+        return Err(format!(
+            "`{command}` failed with {status}: {}",
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+    let stdout = std::str::from_utf8(&stdout)
+        .map_err(|_| format!("the output of `{command}` isn't valid UTF-8"))?;
+    Ok(Zeroizing::new(stdout.trim().to_string()))
+}
+
+/// Run the command with `sh`, in its own process group so that it can be killed along
+/// with anything it started.
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).process_group(0);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Kill the command and anything it started, even if the shell has already exited.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    // The process group has the same id as the shell, and lives on as long as any process
+    // in it does.
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill has no memory-safety requirements, it only takes a process group id
+        // and a signal.
+        unsafe { libc::kill(-group, libc::SIGKILL) };
+    }
+    let _ = child.wait();
+}
+
+#[cfg(windows)]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn output_is_trimmed() {
+        assert_eq!(*run("echo '  secret  '", TIMEOUT).unwrap(), "secret");
+    }
+
+    #[test]
+    fn failure_includes_stderr() {
+        let error = run("echo out; echo oops >&2; exit 3", TIMEOUT).unwrap_err();
+        assert_eq!(
+            error,
+            "`echo out; echo oops >&2; exit 3` failed with exit status: 3: oops"
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let start = Instant::now();
+        let error = run("sleep 5", Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error, "`sleep 5` didn't finish within 200ms");
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn timeout_covers_background_processes() {
+        // The shell exits right away, but the background process keeps its stdout open.
+        let start = Instant::now();
+        let error = run("echo hi; sleep 5 &", Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error, "`echo hi; sleep 5 &` didn't finish within 200ms");
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+}
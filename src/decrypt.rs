@@ -0,0 +1,259 @@
+//! Decrypting encrypted .netrc files.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use age::armor::ArmoredReader;
+use age::{Decryptor, IdentityFile};
+use zeroize::Zeroizing;
+
+/// How to decrypt encrypted .netrc files, which are recognized by their extension.
+pub struct Decrypt {
+    /// The gpg binary used to decrypt `.gpg` files.
+    pub gpg: PathBuf,
+    /// The identity file used to decrypt `.age` files.
+    pub age_identity: Option<PathBuf>,
+}
+
+impl Decrypt {
+    /// Whether the file at `path` is encrypted.
+    pub fn is_encrypted(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == "gpg" || extension == "age")
+    }
+
+    /// Decrypt the `content` of the file at `path`, keeping the decrypted content in memory
+    /// only.
+    pub fn decrypt(&self, path: &Path, content: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        if path.extension().is_some_and(|extension| extension == "age") {
+            self.decrypt_age(content)
+        } else {
+            self.decrypt_gpg(content)
+        }
+        .map_err(|e| format!("unable to decrypt {}: {e}", path.display()))
+    }
+
+    fn decrypt_gpg(&self, content: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        // Cargo talks to credential providers over stdin and stdout, so gpg gets the file
+        // through a pipe of its own, and `--batch` keeps it from asking questions. It can
+        // still ask for a passphrase through the gpg agent.
+        let mut child = Command::new(&self.gpg)
+            .args(["--batch", "--quiet", "--decrypt"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("unable to run {}: {e}", self.gpg.display()))?;
+        let mut stdin = child.stdin.take().unwrap();
+        // The content is written while the output is read, so that neither pipe fills up.
+        // If gpg exits early, the failure is reported from its status instead.
+        let output = thread::scope(|scope| {
+            scope.spawn(move || stdin.write_all(content));
+            child.wait_with_output()
+        })
+        .map_err(|e| format!("unable to run {}: {e}", self.gpg.display()))?;
+        let stdout = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                self.gpg.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(stdout)
+    }
+
+    /// Decrypt an age file, which may be armored, with the identities in the identity file.
+    fn decrypt_age(&self, content: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        let identity = self
+            .age_identity
+            .as_ref()
+            .ok_or("no age identity, pass `--age-identity` or set AGE_IDENTITY")?;
+        let identities = File::open(identity)
+            .and_then(|file| IdentityFile::from_buffer(BufReader::new(file)))
+            .map_err(|e| format!("unable to read {}: {e}", identity.display()))?
+            .into_identities()
+            .map_err(|e| format!("unable to read {}: {e}", identity.display()))?;
+
+        // The decrypted content is smaller than the encrypted one, so the buffer never has to
+        // grow.
+        let mut decrypted = Zeroizing::new(Vec::with_capacity(content.len()));
+        Decryptor::new_buffered(ArmoredReader::new(content))
+            .and_then(|decryptor| {
+                decryptor.decrypt(identities.iter().map(|identity| identity.as_ref() as _))
+            })
+            .map_err(|e| e.to_string())?
+            .read_to_end(&mut decrypted)
+            .map_err(|e| e.to_string())?;
+        Ok(decrypted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use age::secrecy::ExposeSecret;
+    use age::x25519::Identity;
+
+    use super::*;
+
+    const NETRC: &[u8] = b"machine example.com login user password secret\n";
+
+    #[test]
+    fn age_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity = Identity::generate();
+        let identity_file = dir.path().join("identity.txt");
+        fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+        let decrypt = Decrypt {
+            gpg: PathBuf::from("gpg"),
+            age_identity: Some(identity_file),
+        };
+
+        let encrypted = age::encrypt(&identity.to_public(), NETRC).unwrap();
+        let path = Path::new(".netrc.age");
+        assert_eq!(*decrypt.decrypt(path, &encrypted).unwrap(), NETRC);
+        let armored = age::encrypt_and_armor(&identity.to_public(), NETRC).unwrap();
+        assert_eq!(*decrypt.decrypt(path, armored.as_bytes()).unwrap(), NETRC);
+
+        // Encrypted for someone else.
+        let other = age::encrypt(&Identity::generate().to_public(), NETRC).unwrap();
+        let error = decrypt.decrypt(path, &other).unwrap_err();
+        assert!(
+            error.starts_with("unable to decrypt .netrc.age: "),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn age_identity_errors() {
+        let encrypted = age::encrypt(&Identity::generate().to_public(), NETRC).unwrap();
+        let path = Path::new(".netrc.age");
+        let decrypt = Decrypt {
+            gpg: PathBuf::from("gpg"),
+            age_identity: None,
+        };
+        assert_eq!(
+            decrypt.decrypt(path, &encrypted).unwrap_err(),
+            "unable to decrypt .netrc.age: no age identity, pass `--age-identity` or set \
+             AGE_IDENTITY"
+        );
+
+        let decrypt = Decrypt {
+            gpg: PathBuf::from("gpg"),
+            age_identity: Some(PathBuf::from("/nonexistent/identity.txt")),
+        };
+        let error = decrypt.decrypt(path, &encrypted).unwrap_err();
+        assert!(
+            error.starts_with(
+                "unable to decrypt .netrc.age: unable to read /nonexistent/identity.txt: "
+            ),
+            "{error}"
+        );
+    }
+
+    /// A gpg with its own home directory, or `None` if gpg isn't installed.
+    #[cfg(unix)]
+    fn gpg(dir: &Path) -> Option<PathBuf> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = dir.join("gnupg");
+        fs::create_dir(&home).unwrap();
+        fs::set_permissions(&home, fs::Permissions::from_mode(0o700)).unwrap();
+        let gpg = dir.join("gpg");
+        fs::write(
+            &gpg,
+            format!(
+                "#!/bin/sh\nexec gpg --homedir '{}' \"$@\"\n",
+                home.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&gpg, fs::Permissions::from_mode(0o700)).unwrap();
+        let generated = Command::new(&gpg)
+            .args(["--batch", "--passphrase", "", "--quick-gen-key"])
+            .args(["test@example.com", "default", "default", "never"])
+            .stderr(Stdio::null())
+            .status();
+        match generated {
+            Ok(status) if status.success() => Some(gpg),
+            _ => None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn gpg_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let Some(gpg) = gpg(dir.path()) else {
+            eprintln!("gpg isn't installed, skipping");
+            return;
+        };
+        let plain = dir.path().join("netrc");
+        fs::write(&plain, NETRC).unwrap();
+        let encrypted = dir.path().join("netrc.gpg");
+        let status = Command::new(&gpg)
+            .args([
+                "--batch",
+                "--yes",
+                "--recipient",
+                "test@example.com",
+                "--output",
+            ])
+            .arg(&encrypted)
+            .arg("--encrypt")
+            .arg(&plain)
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let decrypt = Decrypt {
+            gpg: gpg.clone(),
+            age_identity: None,
+        };
+        let content = fs::read(&encrypted).unwrap();
+        assert_ne!(content, fs::read(&plain).unwrap());
+        assert_eq!(
+            *decrypt.decrypt(&encrypted, &content).unwrap(),
+            fs::read(&plain).unwrap()
+        );
+        // gpg only gets the content, the file isn't read again.
+        fs::remove_file(&encrypted).unwrap();
+        assert_eq!(*decrypt.decrypt(&encrypted, &content).unwrap(), NETRC);
+
+        // A file that isn't encrypted.
+        let error = decrypt.decrypt(&plain, b"").unwrap_err();
+        assert!(
+            error.starts_with(&format!(
+                "unable to decrypt {}: {} failed: ",
+                plain.display(),
+                gpg.display()
+            )),
+            "{error}"
+        );
+
+        let _ = Command::new("gpgconf")
+            .env("GNUPGHOME", dir.path().join("gnupg"))
+            .args(["--kill", "gpg-agent"])
+            .status();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missing_gpg() {
+        let decrypt = Decrypt {
+            gpg: PathBuf::from("/nonexistent/gpg"),
+            age_identity: None,
+        };
+        let error = decrypt.decrypt(Path::new(".netrc.gpg"), b"").unwrap_err();
+        assert!(
+            error.starts_with("unable to decrypt .netrc.gpg: unable to run /nonexistent/gpg: "),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Expanding environment variables in .netrc values, see `--expand-env`.
+
+use std::env::{self, VarError};
+
+use zeroize::Zeroizing;
+
+/// Expand `${VAR}` and `${VAR:-default}` in a value.
+///
+/// As in the shell, the default is used if the variable is unset or empty. A `$` that isn't
+/// followed by `{` is kept as is.
+pub fn expand(value: &str) -> Result<Zeroizing<String>, String> {
+    let mut expanded = Zeroizing::new(String::with_capacity(value.len()));
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let expression = &rest[start + 2..];
+        let end = expression
+            .find('}')
+            // The value may be a secret, so it isn't part of the error.
+            .ok_or("unterminated `${`")?;
+        let (name, default) = match expression[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expression[..end], None),
+        };
+        match (env::var(name), default) {
+            (Ok(variable), Some(default)) if variable.is_empty() => expanded.push_str(default),
+            (Ok(variable), _) => expanded.push_str(&Zeroizing::new(variable)),
+            (Err(VarError::NotPresent), Some(default)) => expanded.push_str(default),
+            (Err(VarError::NotPresent), None) => {
+                return Err(format!("the environment variable `{name}` is not set"));
+            }
+            (Err(VarError::NotUnicode(_)), _) => {
+                return Err(format!(
+                    "the environment variable `{name}` isn't valid unicode"
+                ));
+            }
+        }
+        rest = &expression[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(value: &str) -> Result<String, String> {
+        // The names are unique to these tests, so nothing else sets them.
+        env::set_var("NETRC_TEST_TOKEN", "s3cr3t");
+        env::set_var("NETRC_TEST_EMPTY", "");
+        env::remove_var("NETRC_TEST_UNSET");
+        super::expand(value).map(|expanded| expanded.to_string())
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(expand("${NETRC_TEST_TOKEN}").unwrap(), "s3cr3t");
+        assert_eq!(expand("a-${NETRC_TEST_TOKEN}-b").unwrap(), "a-s3cr3t-b");
+        assert_eq!(expand("${NETRC_TEST_EMPTY}").unwrap(), "");
+        assert_eq!(expand("${NETRC_TEST_TOKEN:-default}").unwrap(), "s3cr3t");
+        assert_eq!(expand("${NETRC_TEST_UNSET:-default}").unwrap(), "default");
+        assert_eq!(expand("${NETRC_TEST_EMPTY:-default}").unwrap(), "default");
+        assert_eq!(expand("${NETRC_TEST_UNSET:-}").unwrap(), "");
+    }
+
+    #[test]
+    fn dollar_without_brace() {
+        assert_eq!(expand("pa$$word").unwrap(), "pa$$word");
+        assert_eq!(expand("$NETRC_TEST_TOKEN").unwrap(), "$NETRC_TEST_TOKEN");
+        assert_eq!(expand("price: 5$").unwrap(), "price: 5$");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            expand("${NETRC_TEST_UNSET}"),
+            Err("the environment variable `NETRC_TEST_UNSET` is not set".to_string())
+        );
+        assert_eq!(
+            expand("${NETRC_TEST_TOKEN"),
+            Err("unterminated `${`".to_string())
+        );
+        assert_eq!(expand("abc${"), Err("unterminated `${`".to_string()));
+    }
+}
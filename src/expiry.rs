@@ -0,0 +1,253 @@
+//! Working out how long cargo may cache a token.
+
+use std::str::FromStr;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use cargo_credential::CacheControl;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+/// How long cargo may cache a token, as given with `--cache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cache {
+    /// Ask for the token every time it is needed.
+    Never,
+    /// Reuse the token for the rest of the cargo invocation.
+    Session,
+    /// Reuse the token for this many seconds.
+    Seconds(u64),
+}
+
+impl FromStr for Cache {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Cache::Never),
+            "session" => Ok(Cache::Session),
+            seconds => seconds
+                .parse()
+                .map(Cache::Seconds)
+                .map_err(|_| "expected `never`, `session` or a number of seconds".to_string()),
+        }
+    }
+}
+
+impl Cache {
+    /// Get the cache control for a token that expires at `expiration`, if known.
+    ///
+    /// The token is never cached past its expiration.
+    pub fn control(self, expiration: Option<OffsetDateTime>) -> CacheControl {
+        let until = match self {
+            Cache::Never => return CacheControl::Never,
+            Cache::Session => None,
+            Cache::Seconds(seconds) => OffsetDateTime::now_utc()
+                .checked_add(Duration::seconds(seconds.try_into().unwrap_or(i64::MAX))),
+        };
+        match until.into_iter().chain(expiration).min() {
+            Some(expiration) => CacheControl::Expires { expiration },
+            None => CacheControl::Session,
+        }
+    }
+}
+
+/// Where the expiration of an entry was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The `expires` field of the entry.
+    Field,
+    /// The `exp` claim of the password, which is a JWT.
+    Jwt,
+}
+
+/// When the credentials of an entry expire.
+#[derive(Debug, Clone, Copy)]
+pub struct Expiration {
+    pub time: OffsetDateTime,
+    pub source: Source,
+}
+
+impl Expiration {
+    /// Refuse expired credentials unless `allow_expired` is set, and warn about credentials
+    /// that expire within `warning` seconds.
+    ///
+    /// `entry` describes the entry in the messages, e.g. "the entry for example.com".
+    pub fn check(
+        &self,
+        warning: Option<u64>,
+        allow_expired: bool,
+        entry: &str,
+    ) -> Result<(), String> {
+        let remaining = self.time - OffsetDateTime::now_utc();
+        if remaining <= Duration::ZERO {
+            if !allow_expired {
+                return Err(format!(
+                    "{}, pass `--allow-expired` to use it anyway",
+                    self.describe(entry, "expired")
+                ));
+            }
+            eprintln!("warning: {}", self.describe(entry, "expired"));
+            return Ok(());
+        }
+        if warning.is_some_and(|warning| remaining.whole_seconds().unsigned_abs() < warning) {
+            eprintln!("warning: {}", self.describe(entry, "expires"));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, entry: &str, verb: &str) -> String {
+        let time = self
+            .time
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| self.time.to_string());
+        match self.source {
+            Source::Field => format!("{entry} {verb} at {time}, according to its `expires` field"),
+            Source::Jwt => format!("the password of {entry} is a JWT that {verb} at {time}"),
+        }
+    }
+}
+
+/// Work out when the credentials of an entry expire.
+///
+/// This is the earlier of the entry's `expires` field, which is an RFC 3339 date or a Unix
+/// timestamp, and the `exp` claim of the password if it is a JWT. The password is only
+/// given with `--jwt`.
+pub fn expiration(
+    expires: Option<&str>,
+    password: Option<&str>,
+) -> Result<Option<Expiration>, String> {
+    let mut expirations = Vec::new();
+    if let Some(expires) = expires {
+        let time = OffsetDateTime::parse(expires, &Rfc3339)
+            .ok()
+            .or_else(|| OffsetDateTime::from_unix_timestamp(expires.parse().ok()?).ok())
+            .ok_or_else(|| {
+                format!(
+                    "invalid `expires` value `{expires}`, expected an RFC 3339 date or a Unix timestamp"
+                )
+            })?;
+        expirations.push(Expiration {
+            time,
+            source: Source::Field,
+        });
+    }
+    if let Some(time) = password.and_then(jwt_expiration) {
+        expirations.push(Expiration {
+            time,
+            source: Source::Jwt,
+        });
+    }
+    Ok(expirations
+        .into_iter()
+        .min_by_key(|expiration| expiration.time))
+}
+
+/// Get the `exp` claim of a JWT.
+///
+/// Only the payload is decoded, the signature isn't verified. Returns `None` if the value
+/// isn't a JWT or doesn't have the claim.
+fn jwt_expiration(token: &str) -> Option<OffsetDateTime> {
+    let [_, payload, _] = token.split('.').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    // `exp` is a number of seconds, which may have a fractional part.
+    let exp = claims.get("exp")?.as_f64()?;
+    OffsetDateTime::from_unix_timestamp(exp as i64).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expiration(offset: Duration) -> Expiration {
+        Expiration {
+            time: OffsetDateTime::now_utc() + offset,
+            source: Source::Field,
+        }
+    }
+
+    #[test]
+    fn parse_cache() {
+        assert_eq!("never".parse(), Ok(Cache::Never));
+        assert_eq!("session".parse(), Ok(Cache::Session));
+        assert_eq!("300".parse(), Ok(Cache::Seconds(300)));
+        for invalid in ["", "5m", "-1", "Never"] {
+            assert_eq!(
+                invalid.parse::<Cache>(),
+                Err("expected `never`, `session` or a number of seconds".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn cache_control() {
+        let expires = |control| match control {
+            CacheControl::Expires { expiration } => expiration - OffsetDateTime::now_utc(),
+            control => panic!("unexpected cache control {control:?}"),
+        };
+        let soon = OffsetDateTime::now_utc() + Duration::seconds(10);
+        let later = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        assert_eq!(Cache::Never.control(None), CacheControl::Never);
+        assert_eq!(Cache::Never.control(Some(soon)), CacheControl::Never);
+        assert_eq!(Cache::Session.control(None), CacheControl::Session);
+        assert_eq!(
+            Cache::Session.control(Some(soon)),
+            CacheControl::Expires { expiration: soon }
+        );
+        // The earlier of the two wins.
+        let remaining = expires(Cache::Seconds(60).control(None));
+        assert!(remaining > Duration::seconds(55) && remaining <= Duration::seconds(60));
+        let remaining = expires(Cache::Seconds(60).control(Some(later)));
+        assert!(remaining > Duration::seconds(55) && remaining <= Duration::seconds(60));
+        let remaining = expires(Cache::Seconds(60).control(Some(soon)));
+        assert!(remaining <= Duration::seconds(10));
+        // Too far in the future to represent.
+        assert_eq!(
+            Cache::Seconds(u64::MAX).control(None),
+            CacheControl::Session
+        );
+    }
+
+    #[test]
+    fn check() {
+        let entry = "the entry for example.com";
+        assert!(expiration(Duration::hours(1))
+            .check(None, false, entry)
+            .is_ok());
+        assert!(expiration(Duration::hours(1))
+            .check(Some(60), false, entry)
+            .is_ok());
+        let error = expiration(-Duration::hours(1))
+            .check(None, false, entry)
+            .unwrap_err();
+        assert!(error.starts_with("the entry for example.com expired at "));
+        assert!(error.ends_with(", pass `--allow-expired` to use it anyway"));
+        assert!(expiration(-Duration::hours(1))
+            .check(None, true, entry)
+            .is_ok());
+    }
+
+    #[test]
+    fn expiration_from_field_and_jwt() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"exp":2000000000}"#);
+        let jwt = format!("e30.{claims}.c2ln");
+        let expiration = super::expiration(Some("2030-01-01T00:00:00Z"), Some(&jwt))
+            .unwrap()
+            .unwrap();
+        assert_eq!(expiration.time.unix_timestamp(), 1893456000);
+        assert!(matches!(expiration.source, Source::Field));
+        let expiration = super::expiration(Some("2100000000"), Some(&jwt))
+            .unwrap()
+            .unwrap();
+        assert_eq!(expiration.time.unix_timestamp(), 2000000000);
+        assert!(matches!(expiration.source, Source::Jwt));
+        assert!(super::expiration(None, Some("not a jwt"))
+            .unwrap()
+            .is_none());
+        assert!(super::expiration(Some("soon"), None).is_err());
+    }
+}
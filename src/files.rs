@@ -0,0 +1,445 @@
+//! Locating, reading and writing .netrc files.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use zeroize::Zeroizing;
+
+use crate::decrypt::Decrypt;
+use crate::netrc::Netrc;
+
+/// A parsed .netrc file and the path it was read from.
+pub struct NetrcFile {
+    pub path: PathBuf,
+    pub netrc: Netrc,
+}
+
+/// How to read .netrc files.
+pub struct ReadOptions {
+    /// How to decrypt encrypted files.
+    pub decrypt: Decrypt,
+    /// Refuse files that other users can access, see [`check_permissions`].
+    pub check_permissions: bool,
+    /// Print which files are read to stderr.
+    pub verbose: bool,
+}
+
+#[cfg(test)]
+impl ReadOptions {
+    /// Options for tests, without decryption or verbose output.
+    pub fn for_tests(check_permissions: bool) -> Self {
+        ReadOptions {
+            decrypt: Decrypt {
+                gpg: PathBuf::from("gpg"),
+                age_identity: None,
+            },
+            check_permissions,
+            verbose: false,
+        }
+    }
+}
+
+/// A location that is searched for a .netrc file.
+#[derive(Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    /// Whether the path was explicitly configured, in which case it must exist.
+    pub explicit: bool,
+}
+
+/// Get the locations to search for .netrc files, in order of precedence.
+///
+/// These are the `--netrc-file` arguments in the order they were given, the `NETRC`
+/// environment variable, `$HOME/.netrc`, `$HOME/_netrc`, `$HOME/.netrc.gpg` and
+/// `$HOME/.netrc.age`.
+pub fn sources(netrc_files: &[PathBuf]) -> Result<Vec<Source>, cargo_credential::Error> {
+    let mut sources: Vec<Source> = netrc_files
+        .iter()
+        .map(|path| Source {
+            path: path.clone(),
+            explicit: true,
+        })
+        .collect();
+    if let Some(path) = std::env::var_os("NETRC").filter(|path| !path.is_empty()) {
+        sources.push(Source {
+            path: PathBuf::from(path),
+            explicit: true,
+        });
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        for name in [".netrc", "_netrc", ".netrc.gpg", ".netrc.age"] {
+            sources.push(Source {
+                path: Path::new(&home).join(name),
+                explicit: false,
+            });
+        }
+    }
+
+    if sources.is_empty() {
+        return Err("unable to locate a .netrc file: neither NETRC nor HOME is set".into());
+    }
+
+    // The same file may be configured more than once, e.g. NETRC=~/.netrc.
+    let mut seen = Vec::new();
+    sources.retain(|source| {
+        let duplicate = seen.contains(&source.path);
+        seen.push(source.path.clone());
+        !duplicate
+    });
+    Ok(sources)
+}
+
+/// The .netrc files, which are read on demand in order of precedence.
+///
+/// A file is only read once the files before it didn't have what was looked for, so a
+/// file that can't be read, decrypted or parsed only matters if it is needed.
+pub struct NetrcFiles {
+    sources: std::vec::IntoIter<Source>,
+    options: ReadOptions,
+    files: Vec<NetrcFile>,
+}
+
+impl NetrcFiles {
+    pub fn new(sources: Vec<Source>, options: ReadOptions) -> Self {
+        NetrcFiles {
+            sources: sources.into_iter(),
+            options,
+            files: Vec::new(),
+        }
+    }
+
+    /// Get the `i`th file that exists, reading more files if needed.
+    ///
+    /// Explicitly configured files must exist.
+    pub fn get(&mut self, i: usize) -> Result<Option<&NetrcFile>, cargo_credential::Error> {
+        while self.files.len() <= i {
+            let Some(source) = self.sources.next() else {
+                return Ok(None);
+            };
+            match read(&source.path, source.explicit, &self.options)? {
+                Some(netrc) => {
+                    if self.options.verbose {
+                        eprintln!("reading {}", source.path.display());
+                    }
+                    self.files.push(NetrcFile {
+                        path: source.path,
+                        netrc,
+                    });
+                }
+                None => {
+                    if self.options.verbose {
+                        eprintln!("skipping {}: file not found", source.path.display());
+                    }
+                }
+            }
+        }
+        Ok(self.files.get(i))
+    }
+
+    /// Take the `i`th file, which must have been read already.
+    pub fn take(mut self, i: usize) -> NetrcFile {
+        self.files.swap_remove(i)
+    }
+}
+
+impl Index<usize> for NetrcFiles {
+    type Output = NetrcFile;
+
+    /// Get the `i`th file, which must have been read already.
+    fn index(&self, i: usize) -> &NetrcFile {
+        &self.files[i]
+    }
+}
+
+/// Read a .netrc file.
+///
+/// Returns `None` if the file doesn't exist, unless it is `required`. Encrypted files are
+/// decrypted first, and the permissions of other files are checked if configured.
+pub fn read(
+    path: &Path,
+    required: bool,
+    options: &ReadOptions,
+) -> Result<Option<Netrc>, cargo_credential::Error> {
+    let content = match fs::read(path) {
+        Ok(content) => Zeroizing::new(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+        Err(e) => return Err(format!("unable to read {}: {e}", path.display()).into()),
+    };
+    let content = if Decrypt::is_encrypted(path) {
+        options.decrypt.decrypt(path, &content)?
+    } else {
+        if options.check_permissions {
+            check_permissions(path)?;
+        }
+        content
+    };
+    let content = std::str::from_utf8(&content)
+        .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    Netrc::parse(content)
+        .map(Some)
+        .map_err(|e| format!("{e} in the file '{}'", path.display()).into())
+}
+
+/// Check that only the current user can access a .netrc file, as curl and ftp do.
+///
+/// The file must be owned by the current user and not be accessible to anyone else. Its
+/// directory must be owned by the current user or root, and other users must not be able to
+/// replace the file, unless the directory is sticky like `/tmp`. For a symlink, this goes
+/// for the directories of both the link and the file it points to.
+///
+/// Other users may read the directory, which is the default for home directories: they
+/// can see that the file exists, but not what's in it.
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    const SKIP: &str = "pass `--insecure-permissions` to read it anyway";
+
+    // SAFETY: geteuid has no preconditions and always succeeds.
+    let user = unsafe { libc::geteuid() };
+    let metadata = |path: &Path| {
+        fs::metadata(path).map_err(|e| format!("unable to read {}: {e}", path.display()))
+    };
+
+    let file = metadata(path)?;
+    if file.uid() != user {
+        return Err(format!(
+            "{} is owned by another user, {SKIP}",
+            path.display()
+        ));
+    }
+    if file.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} can be accessed by other users, run `chmod 600 {}` or {SKIP}",
+            path.display(),
+            path.display()
+        ));
+    }
+
+    // If the file is a symlink, e.g. into a dotfiles checkout, both the directory of the
+    // link and that of the file it points to matter.
+    let target =
+        fs::canonicalize(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    let mut directories = vec![match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    }];
+    if let Some(directory) = target.parent() {
+        directories.push(directory);
+    }
+    for directory in directories {
+        let metadata = metadata(directory)?;
+        if metadata.uid() != user && metadata.uid() != 0 {
+            return Err(format!(
+                "the directory of {}, {}, is owned by another user, {SKIP}",
+                path.display(),
+                directory.display()
+            ));
+        }
+        if metadata.mode() & 0o022 != 0 && metadata.mode() & 0o1000 == 0 {
+            return Err(format!(
+                "the directory of {}, {}, can be changed by other users, {SKIP}",
+                path.display(),
+                directory.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Write a .netrc file, creating it if needed.
+///
+/// The file is written to a temporary file next to it, which then replaces it, so that the
+/// original is never left half-written. It is only readable by the current user, since it
+/// contains passwords. If the path is a symlink, its target is replaced. Encrypted files
+/// can't be written.
+pub fn write(path: &Path, netrc: &Netrc) -> Result<(), cargo_credential::Error> {
+    if Decrypt::is_encrypted(path) {
+        return Err(format!(
+            "unable to write {}: encrypted .netrc files can't be changed",
+            path.display()
+        )
+        .into());
+    }
+
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let error = |e: io::Error| format!("unable to write {}: {e}", path.display());
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| error(io::ErrorKind::InvalidInput.into()))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp = path.with_file_name(temp_name);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let content = Zeroizing::new(netrc.to_string());
+    let result = options
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.map_err(|e| error(e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn netrc(src: &str) -> Netrc {
+        Netrc::parse(src).unwrap()
+    }
+
+    #[test]
+    fn write_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".netrc");
+        fs::write(&path, "machine old.com\n").unwrap();
+
+        write(&path, &netrc("machine new.com\n")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "machine new.com\n");
+        // The temporary file is gone.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("netrc");
+        let link = dir.path().join(".netrc");
+        fs::write(&target, "machine old.com\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write(&link, &netrc("machine new.com\n")).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "machine new.com\n");
+    }
+
+    #[test]
+    fn write_refuses_encrypted_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".netrc.gpg");
+        assert!(write(&path, &netrc("machine new.com\n")).is_err());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    fn read_with_modes(
+        directory_mode: u32,
+        file_mode: u32,
+        check_permissions: bool,
+    ) -> Result<(), String> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let directory = dir.path().join("home");
+        fs::create_dir(&directory).unwrap();
+        let path = directory.join(".netrc");
+        fs::write(&path, "machine example.com\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(file_mode)).unwrap();
+        fs::set_permissions(&directory, fs::Permissions::from_mode(directory_mode)).unwrap();
+        read(&path, true, &ReadOptions::for_tests(check_permissions))
+            .map(|netrc| assert!(netrc.is_some()))
+            .map_err(|e| e.to_string().replace(&path.display().to_string(), "FILE"))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_permissions() {
+        assert_eq!(read_with_modes(0o700, 0o600, true), Ok(()));
+        assert_eq!(read_with_modes(0o700, 0o400, true), Ok(()));
+        for mode in [0o640, 0o604, 0o644, 0o660] {
+            assert_eq!(
+                read_with_modes(0o700, mode, true),
+                Err(
+                    "FILE can be accessed by other users, run `chmod 600 FILE` or pass \
+                     `--insecure-permissions` to read it anyway"
+                        .to_string()
+                ),
+                "{mode:o}"
+            );
+            assert_eq!(read_with_modes(0o700, mode, false), Ok(()), "{mode:o}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_permissions() {
+        // Others may read the directory, but only change it if it is sticky.
+        for mode in [0o700, 0o755, 0o1777, 0o1770] {
+            assert_eq!(read_with_modes(mode, 0o600, true), Ok(()), "{mode:o}");
+        }
+        for mode in [0o777, 0o775, 0o757] {
+            let error = read_with_modes(mode, 0o600, true).unwrap_err();
+            assert!(
+                error.ends_with(
+                    "home, can be changed by other users, pass `--insecure-permissions` to \
+                     read it anyway"
+                ),
+                "{mode:o}: {error}"
+            );
+            assert_eq!(read_with_modes(mode, 0o600, false), Ok(()), "{mode:o}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().join("home");
+        let dotfiles = dir.path().join("dotfiles");
+        fs::create_dir(&home).unwrap();
+        fs::create_dir(&dotfiles).unwrap();
+        let target = dotfiles.join("netrc");
+        fs::write(&target, "machine example.com\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = home.join(".netrc");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let read = |check_permissions| {
+            read(&link, true, &ReadOptions::for_tests(check_permissions)).map(|_| ())
+        };
+        for (home_mode, dotfiles_mode) in [(0o700, 0o700), (0o755, 0o755)] {
+            fs::set_permissions(&home, fs::Permissions::from_mode(home_mode)).unwrap();
+            fs::set_permissions(&dotfiles, fs::Permissions::from_mode(dotfiles_mode)).unwrap();
+            assert!(read(true).is_ok());
+        }
+        for (home_mode, dotfiles_mode, changed) in
+            [(0o700, 0o777, "dotfiles"), (0o777, 0o700, "home")]
+        {
+            fs::set_permissions(&home, fs::Permissions::from_mode(home_mode)).unwrap();
+            fs::set_permissions(&dotfiles, fs::Permissions::from_mode(dotfiles_mode)).unwrap();
+            let error = read(true).unwrap_err().to_string();
+            let expected = format!("{changed}, can be changed by other users");
+            assert!(error.contains(&expected), "{error}");
+            assert!(read(false).is_ok());
+        }
+    }
+}
@@ -0,0 +1,665 @@
+//! Handling of the `--format` token template.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use cargo_credential::Secret;
+use handlebars::template::{HelperTemplate, Parameter, Template, TemplateElement};
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonValue, PathAndJson, PathSeg, RenderContext,
+    RenderError, RenderErrorReason, ScopedJson,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+/// The variables that can be used in the token format.
+pub const VARIABLES: [&str; 3] = ["login", "account", "password"];
+
+/// The variables that describe the registry and the operation rather than the .netrc entry.
+pub const CONTEXT: [&str; 6] = [
+    "registry_name",
+    "index_url",
+    "host",
+    "port",
+    "path",
+    "operation",
+];
+
+/// The variable that holds the custom fields of the .netrc entry, e.g. `{{fields.tenant}}`.
+const FIELDS: &str = "fields";
+
+/// The values a token is rendered from.
+#[derive(Default, Serialize)]
+pub struct Data<'a> {
+    /// The variables, leaving out the .netrc fields that the entry doesn't have.
+    #[serde(flatten)]
+    pub variables: HashMap<&'a str, Secret<Zeroizing<String>>>,
+    /// The custom fields of the .netrc entry.
+    pub fields: HashMap<&'a str, Secret<Zeroizing<String>>>,
+}
+
+/// Characters that are percent-encoded by the `urlencode` helper: everything except
+/// the unreserved characters of RFC 3986.
+const URL_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Get the value of a helper parameter as a string.
+///
+/// Unlike the helpers generated by `handlebars_helper!`, this reports a variable that has
+/// no value as missing, naming the variable, as strict mode does for plain expressions.
+fn param_string(param: &PathAndJson<'_>) -> Result<String, RenderError> {
+    if param.is_value_missing() {
+        return Err(RenderErrorReason::MissingVariable(param.relative_path().cloned()).into());
+    }
+    Ok(match param.value() {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => String::new(),
+        other => other.to_string(),
+    })
+}
+
+/// A helper that transforms its only parameter.
+struct StringHelper {
+    name: &'static str,
+    transform: fn(&str) -> String,
+}
+
+impl HelperDef for StringHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let param = helper
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex(self.name, 0))?;
+        let value = (self.transform)(&Zeroizing::new(param_string(param)?));
+        Ok(ScopedJson::Derived(JsonValue::String(value)))
+    }
+}
+
+/// The `concat` helper, which joins all of its parameters.
+struct Concat;
+
+impl HelperDef for Concat {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = helper
+            .params()
+            .iter()
+            .map(param_string)
+            .collect::<Result<String, _>>()?;
+        Ok(ScopedJson::Derived(JsonValue::String(value)))
+    }
+}
+
+/// The helpers that transform a single string.
+const STRING_HELPERS: [StringHelper; 8] = [
+    StringHelper {
+        name: "base64",
+        transform: |s| STANDARD.encode(s),
+    },
+    StringHelper {
+        name: "base64url",
+        transform: |s| URL_SAFE_NO_PAD.encode(s),
+    },
+    StringHelper {
+        name: "urlencode",
+        transform: |s| utf8_percent_encode(s, URL_ENCODE).to_string(),
+    },
+    StringHelper {
+        name: "hex",
+        transform: |s| to_hex(s.as_bytes()),
+    },
+    StringHelper {
+        name: "sha256",
+        transform: |s| to_hex(&Sha256::digest(s)),
+    },
+    StringHelper {
+        name: "trim",
+        transform: |s| s.trim().to_string(),
+    },
+    StringHelper {
+        name: "upper",
+        transform: str::to_uppercase,
+    },
+    StringHelper {
+        name: "lower",
+        transform: str::to_lowercase,
+    },
+];
+
+/// Create the handlebars registry used to render tokens.
+///
+/// Values aren't HTML-escaped, since tokens aren't HTML. Strict mode is enabled, so using
+/// a variable that has no value is an error rather than an empty string. The following
+/// helpers are available:
+/// - `base64` and `base64url`, the standard and unpadded url-safe base64 encodings,
+/// - `urlencode`, which percent-encodes everything but unreserved characters,
+/// - `hex` and `sha256`, which both produce lowercase hex,
+/// - `trim`, `upper` and `lower`,
+/// - `concat`, which joins all of its arguments.
+fn handlebars() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.register_escape_fn(handlebars::no_escape);
+    for helper in STRING_HELPERS {
+        handlebars.register_helper(helper.name, Box::new(helper));
+    }
+    handlebars.register_helper("concat", Box::new(Concat));
+    handlebars
+}
+
+/// A token format, compiled along with its partials.
+pub struct Format {
+    /// The source of the format itself, without its partials.
+    source: String,
+    /// Whether the format or one of its partials mentions the operation.
+    uses_operation: bool,
+    /// The registry the format is compiled into, under the empty name, which can't clash
+    /// with a partial.
+    handlebars: Handlebars<'static>,
+}
+
+impl Format {
+    /// Compile a token format given on the command line.
+    pub fn new(source: &str) -> Result<Self, String> {
+        let template =
+            compile(source).map_err(|e| format!("{e} in the token format `{source}`"))?;
+        Ok(Format::compiled(source.to_string(), template))
+    }
+
+    /// Read and compile a token format from a file.
+    ///
+    /// The partials it uses are read from the `.hbs` files in the same directory, named
+    /// after the file without the extension, so `{{> header}}` includes `header.hbs`. Other
+    /// files in the directory are left alone. Trailing newlines are removed from the format
+    /// and the partials.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let source = read_template(path)?;
+        let template =
+            compile(&source).map_err(|e| format!("{e} in the file '{}'", path.display()))?;
+        let mut partials = Vec::new();
+        collect_partials(&template, &mut partials);
+        let mut format = Format::compiled(source, template);
+
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        // Partials can use other partials.
+        while let Some(name) = partials.pop() {
+            if format.handlebars.has_template(&name) {
+                continue;
+            }
+            let partial = directory.join(format!("{name}.hbs"));
+            let source = read_template(&partial)?;
+            let template =
+                compile(&source).map_err(|e| format!("{e} in the file '{}'", partial.display()))?;
+            collect_partials(&template, &mut partials);
+            format.uses_operation |= uses_operation(&template);
+            format.handlebars.register_template(&name, template);
+        }
+        Ok(format)
+    }
+
+    fn compiled(source: String, template: Template) -> Self {
+        let uses_operation = uses_operation(&template);
+        let mut handlebars = handlebars();
+        handlebars.register_template("", template);
+        Format {
+            uses_operation,
+            source,
+            handlebars,
+        }
+    }
+
+    /// The source of the format, without its partials.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the token depends on the operation, and so can't be reused for other ones.
+    pub fn uses_operation(&self) -> bool {
+        self.uses_operation
+    }
+
+    /// Render a token.
+    pub fn render(&self, data: &Data<'_>) -> Result<Zeroizing<String>, RenderError> {
+        // Handlebars renders from a JSON copy of the data, which holds the secrets too.
+        let mut context = Context::wraps(data)?;
+        let token = self
+            .handlebars
+            .render_with_context("", &context)
+            .map(Zeroizing::new);
+        wipe(context.data_mut());
+        token
+    }
+}
+
+/// Wipe the strings in a JSON value.
+fn wipe(value: &mut JsonValue) {
+    match value {
+        JsonValue::String(string) => string.zeroize(),
+        JsonValue::Array(values) => values.iter_mut().for_each(wipe),
+        JsonValue::Object(values) => values.values_mut().for_each(wipe),
+        _ => {}
+    }
+}
+
+/// Read a template file, without its trailing newlines.
+fn read_template(path: &Path) -> Result<String, String> {
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    Ok(source.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Compile a token format and check that it only uses known variables.
+///
+/// Paths inside `#each` and `#with` blocks, and paths that don't start with a name, such
+/// as `this` or `@root`, aren't checked.
+fn compile(source: &str) -> Result<Template, String> {
+    let template = Template::compile(source).map_err(|e| format!("invalid syntax: {e}"))?;
+    check_template(&template)?;
+    Ok(template)
+}
+
+fn check_template(template: &Template) -> Result<(), String> {
+    for element in &template.elements {
+        match element {
+            TemplateElement::Expression(helper)
+            | TemplateElement::HtmlExpression(helper)
+            | TemplateElement::HelperBlock(helper) => check_helper(helper)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_helper(helper: &HelperTemplate) -> Result<(), String> {
+    for param in std::iter::once(&helper.name)
+        .chain(&helper.params)
+        .chain(helper.hash.values())
+    {
+        check_parameter(param)?;
+    }
+
+    // The context of these blocks is no longer the variables.
+    let name = helper.name.as_name();
+    if helper.block_param.is_some() || name == Some("each") || name == Some("with") {
+        return Ok(());
+    }
+    for template in helper.template.iter().chain(&helper.inverse) {
+        check_template(template)?;
+    }
+    Ok(())
+}
+
+fn check_parameter(param: &Parameter) -> Result<(), String> {
+    match param {
+        Parameter::Path(handlebars::Path::Relative((segments, _))) => match segments.first() {
+            Some(PathSeg::Named(name))
+                if !VARIABLES.contains(&name.as_str())
+                    && !CONTEXT.contains(&name.as_str())
+                    && name != FIELDS =>
+            {
+                Err(format!("unknown variable `{name}`"))
+            }
+            _ => Ok(()),
+        },
+        Parameter::Subexpression(subexpression) => match subexpression.as_element() {
+            TemplateElement::Expression(helper) => check_helper(helper),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Collect the names of the partials a template includes, e.g. `header` for `{{> header}}`.
+///
+/// Partials defined in the template with `{{#*inline}}`, `@partial-block` and partials
+/// whose name is computed aren't included.
+fn collect_partials(template: &Template, partials: &mut Vec<String>) {
+    let mut inline = Vec::new();
+    let mut included = Vec::new();
+    collect_partials_in(template, &mut inline, &mut included);
+    partials.extend(included.into_iter().filter(|name| !inline.contains(name)));
+}
+
+fn collect_partials_in(template: &Template, inline: &mut Vec<String>, included: &mut Vec<String>) {
+    for element in &template.elements {
+        match element {
+            TemplateElement::PartialExpression(partial)
+            | TemplateElement::PartialBlock(partial) => {
+                if let Some(name) = partial.name.as_name().filter(|name| !name.starts_with('@')) {
+                    included.push(name.to_string());
+                }
+                partial
+                    .template
+                    .iter()
+                    .for_each(|template| collect_partials_in(template, inline, included));
+            }
+            TemplateElement::DecoratorBlock(decorator) => {
+                if decorator.name.as_name() == Some("inline") {
+                    if let Some(Parameter::Literal(JsonValue::String(name))) =
+                        decorator.params.first()
+                    {
+                        inline.push(name.clone());
+                    }
+                }
+                decorator
+                    .template
+                    .iter()
+                    .for_each(|template| collect_partials_in(template, inline, included));
+            }
+            TemplateElement::HelperBlock(helper) => helper
+                .template
+                .iter()
+                .chain(&helper.inverse)
+                .for_each(|template| collect_partials_in(template, inline, included)),
+            _ => {}
+        }
+    }
+}
+
+/// Whether a template refers to the `operation` variable.
+///
+/// Unlike the check for unknown variables, this looks inside every block, since
+/// `{{@root.operation}}` can be used where the context is something else.
+fn uses_operation(template: &Template) -> bool {
+    template.elements.iter().any(|element| match element {
+        TemplateElement::Expression(helper)
+        | TemplateElement::HtmlExpression(helper)
+        | TemplateElement::HelperBlock(helper) => helper_uses_operation(helper),
+        TemplateElement::DecoratorExpression(decorator)
+        | TemplateElement::DecoratorBlock(decorator)
+        | TemplateElement::PartialExpression(decorator)
+        | TemplateElement::PartialBlock(decorator) => {
+            std::iter::once(&decorator.name)
+                .chain(&decorator.params)
+                .chain(decorator.hash.values())
+                .any(parameter_uses_operation)
+                || decorator.template.as_ref().is_some_and(uses_operation)
+        }
+        _ => false,
+    })
+}
+
+fn helper_uses_operation(helper: &HelperTemplate) -> bool {
+    std::iter::once(&helper.name)
+        .chain(&helper.params)
+        .chain(helper.hash.values())
+        .any(parameter_uses_operation)
+        || helper
+            .template
+            .iter()
+            .chain(&helper.inverse)
+            .any(uses_operation)
+}
+
+fn parameter_uses_operation(param: &Parameter) -> bool {
+    match param {
+        Parameter::Path(handlebars::Path::Relative((segments, _))) => segments
+            .iter()
+            .any(|segment| matches!(segment, PathSeg::Named(name) if name == "operation")),
+        // e.g. `{{lookup @root "operation"}}`.
+        Parameter::Literal(JsonValue::String(name)) => name == "operation",
+        Parameter::Subexpression(subexpression) => match subexpression.as_element() {
+            TemplateElement::Expression(helper) => helper_uses_operation(helper),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// A piece of a token format.
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Variable(&'a str),
+}
+
+/// Split a format into literals and variables.
+///
+/// Returns `None` if the format uses anything other than plain `{{variable}}`
+/// expressions, such as helpers or block expressions.
+fn segments(format: &str) -> Option<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            segments.push(Segment::Literal(&rest[..start]));
+        }
+        let (open, close) = if rest[start..].starts_with("{{{") {
+            ("{{{", "}}}")
+        } else {
+            ("{{", "}}")
+        };
+        let expression = &rest[start + open.len()..];
+        let end = expression.find(close)?;
+        let name = expression[..end].trim();
+        if !VARIABLES.contains(&name) {
+            return None;
+        }
+        segments.push(Segment::Variable(name));
+        rest = &expression[end + close.len()..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+    Some(segments)
+}
+
+/// Returns the variables used by the format, in order of first use.
+///
+/// Returns `None` if the format can't be reversed, see [`parse_token`].
+pub fn variables(format: &str) -> Option<Vec<&str>> {
+    let mut variables = Vec::new();
+    for segment in segments(format)? {
+        if let Segment::Variable(name) = segment {
+            if !variables.contains(&name) {
+                variables.push(name);
+            }
+        }
+    }
+    Some(variables)
+}
+
+/// Reverse a rendered token back into the variables it was rendered from.
+///
+/// This only works for formats made of literals and plain `{{variable}}` expressions
+/// where every variable is followed by a literal or the end of the format, e.g.
+/// `{{login}}:{{password}}` or `Bearer {{password}}`. A variable extends up to the
+/// first occurrence of the literal that follows it.
+pub fn parse_token<'f>(format: &'f str, token: &str) -> Option<Vec<(&'f str, Zeroizing<String>)>> {
+    let segments = segments(format)?;
+    let mut fields: Vec<(&str, Zeroizing<String>)> = Vec::new();
+    let mut rest = token;
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Literal(literal) => rest = rest.strip_prefix(literal)?,
+            Segment::Variable(name) => {
+                let end = match segments.get(i + 1) {
+                    None => rest.len(),
+                    Some(Segment::Literal(literal)) if i + 2 == segments.len() => {
+                        rest.strip_suffix(literal)?.len()
+                    }
+                    Some(Segment::Literal(literal)) => rest.find(literal)?,
+                    // Two adjacent variables are ambiguous.
+                    Some(Segment::Variable(_)) => return None,
+                };
+                let value = &rest[..end];
+                match fields.iter().find(|(field, _)| field == name) {
+                    Some((_, existing)) if existing.as_str() != value => return None,
+                    Some(_) => {}
+                    None => fields.push((name, Zeroizing::new(value.to_string()))),
+                }
+                rest = &rest[end..];
+            }
+        }
+    }
+    rest.is_empty().then_some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: &str, password: &str) -> String {
+        let mut data = Data::default();
+        data.variables.insert(
+            "password",
+            Secret::from(Zeroizing::new(password.to_string())),
+        );
+        data.variables
+            .insert("login", Secret::from(Zeroizing::new("user".to_string())));
+        Format::new(format)
+            .unwrap()
+            .render(&data)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn helpers() {
+        for (format, password, token) in [
+            ("{{base64 password}}", "a?b>", "YT9iPg=="),
+            ("{{base64url password}}", "a?b>", "YT9iPg"),
+            ("{{urlencode password}}", "a b/c~d-e", "a%20b%2Fc~d-e"),
+            ("{{urlencode password}}", "é", "%C3%A9"),
+            ("{{hex password}}", "AZ\n", "415a0a"),
+            (
+                "{{sha256 password}}",
+                "abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            ("{{trim password}}", " \tpass\n", "pass"),
+            ("{{upper password}}", "Pass", "PASS"),
+            ("{{lower password}}", "Pass", "pass"),
+            ("{{concat login \":\" password}}", "pass", "user:pass"),
+            (
+                "{{base64 (concat login \":\" password)}}",
+                "pass",
+                "dXNlcjpwYXNz",
+            ),
+            ("{{upper (trim password)}}", " pass ", "PASS"),
+        ] {
+            assert_eq!(render(format, password), token, "{format}");
+        }
+    }
+
+    #[test]
+    fn uses_operation() {
+        for (format, uses_operation) in [
+            ("{{password}}", false),
+            ("operation {{password}}", false),
+            ("{{!-- no operation --}}{{password}}", false),
+            ("{{operation}}", true),
+            (
+                "{{#if (eq operation \"publish\")}}{{password}}{{/if}}",
+                true,
+            ),
+            ("{{upper (concat login operation)}}", true),
+            ("{{#with fields}}{{@root.operation}}{{/with}}", true),
+            ("{{#each fields}}{{../operation}}{{/each}}", true),
+        ] {
+            let format = Format::new(format).unwrap();
+            assert_eq!(
+                format.uses_operation(),
+                uses_operation,
+                "{}",
+                format.source()
+            );
+        }
+    }
+
+    #[test]
+    fn helpers_report_missing_variables() {
+        for format in [
+            "{{base64 account}}",
+            "{{upper account}}",
+            "{{concat login account}}",
+            "{{base64 (concat account)}}",
+        ] {
+            let mut data = Data::default();
+            data.variables
+                .insert("login", Secret::from(Zeroizing::new("user".to_string())));
+            let error = Format::new(format).unwrap().render(&data).unwrap_err();
+            match error.reason() {
+                RenderErrorReason::MissingVariable(Some(name)) => assert_eq!(name, "account"),
+                reason => panic!("unexpected error for {format}: {reason:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.hbs");
+        fs::write(&path, "{{> scheme}} {{> credentials}}\n\n").unwrap();
+        fs::write(dir.path().join("scheme.hbs"), "Basic\r\n").unwrap();
+        fs::write(
+            dir.path().join("credentials.hbs"),
+            "{{#*inline \"separator\"}}:{{/inline}}{{> user}}{{> separator}}{{password}}",
+        )
+        .unwrap();
+        fs::write(dir.path().join("user.hbs"), "{{login}}@{{operation}}").unwrap();
+        // Unrelated templates are never read.
+        fs::write(dir.path().join("page.hbs"), "{{title}}").unwrap();
+
+        let format = Format::from_file(&path).unwrap();
+        assert_eq!(format.source(), "{{> scheme}} {{> credentials}}");
+        assert!(format.uses_operation());
+        let mut data = Data::default();
+        for (name, value) in [
+            ("login", "user"),
+            ("password", "pass"),
+            ("operation", "read"),
+        ] {
+            data.variables
+                .insert(name, Secret::from(Zeroizing::new(value.to_string())));
+        }
+        assert_eq!(*format.render(&data).unwrap(), "Basic user@read:pass");
+    }
+
+    #[test]
+    fn from_file_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.hbs");
+        let partial = dir.path().join("missing.hbs");
+        fs::write(&path, "{{> missing}}").unwrap();
+        let error = Format::from_file(&path).err().unwrap();
+        assert!(
+            error.starts_with(&format!("unable to read {}: ", partial.display())),
+            "{error}"
+        );
+
+        fs::write(&partial, "{{title}}").unwrap();
+        assert_eq!(
+            Format::from_file(&path).err().unwrap(),
+            format!(
+                "unknown variable `title` in the file '{}'",
+                partial.display()
+            )
+        );
+    }
+}
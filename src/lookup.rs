@@ -0,0 +1,506 @@
+//! Finding the .netrc entry to use for a registry.
+
+use cargo_credential::RegistryInfo;
+use url::{Host, Url};
+
+use crate::files::NetrcFiles;
+use crate::netrc::Netrc;
+
+/// The names of the operations cargo asks for credentials for.
+pub const OPERATIONS: [&str; 5] = ["read", "publish", "yank", "unyank", "owners"];
+
+/// How to find the entry for a registry.
+pub struct Lookup {
+    /// The host of the registry's index url.
+    host: String,
+    /// The explicit port of the registry's index url.
+    port: Option<u16>,
+    /// The path of the registry's index url.
+    path: String,
+    /// The machine names that match the registry exactly, most specific first.
+    machines: Vec<String>,
+    /// The machine name to use when adding a new entry.
+    new_machine: String,
+    /// Whether the machine name was given explicitly, rather than derived from the registry.
+    explicit: bool,
+    /// Fall back to the `default` entry.
+    pub allow_default: bool,
+    /// Allow `*.example.com` and `.example.com` machine names to match subdomains.
+    pub wildcards: bool,
+    /// Let a registry whose index url has an explicit port use the entry for the bare host.
+    pub bare_host: bool,
+    /// The operation the credentials are for, if any.
+    pub operation: Option<&'static str>,
+}
+
+impl Lookup {
+    /// Create a lookup for exact matches of the registry.
+    ///
+    /// If `machine` is given, only entries with that name match. Otherwise, in order of
+    /// preference, `cargo:<registry name>`, `host:port` if the index url has an explicit
+    /// port, and the bare host match.
+    pub fn new(
+        registry: &RegistryInfo<'_>,
+        machine: Option<&str>,
+    ) -> Result<Self, cargo_credential::Error> {
+        let url = Url::parse(registry.index_url)
+            .map_err(|e| cargo_credential::Error::Other(Box::new(e)))?;
+        let (host, host_with_port) = match url.host() {
+            Some(Host::Domain(host)) => (host.to_string(), host.to_string()),
+            Some(Host::Ipv4(ip)) => (ip.to_string(), ip.to_string()),
+            Some(Host::Ipv6(ip)) => (ip.to_string(), format!("[{ip}]")),
+            _ => return Err(cargo_credential::Error::UrlNotSupported),
+        };
+
+        let mut machines = Vec::new();
+        if let Some(machine) = machine {
+            machines.push(machine.to_string());
+        } else {
+            if let Some(name) = registry.name {
+                machines.push(format!("cargo:{name}"));
+            }
+            if let Some(port) = url.port() {
+                machines.push(format!("{host_with_port}:{port}"));
+            }
+            machines.push(host.clone());
+        }
+        // New entries are added for the host rather than `cargo:<registry name>`, so that
+        // they work with other programs that read the file.
+        let new_machine = match (machine, url.port()) {
+            (Some(machine), _) => machine.to_string(),
+            (None, Some(port)) => format!("{host_with_port}:{port}"),
+            (None, None) => host.clone(),
+        };
+
+        Ok(Lookup {
+            host,
+            port: url.port(),
+            path: url.path().to_string(),
+            machines,
+            new_machine,
+            explicit: machine.is_some(),
+            allow_default: false,
+            wildcards: false,
+            bare_host: true,
+            operation: None,
+        })
+    }
+
+    /// The host of the registry's index url.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The explicit port of the registry's index url.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The path of the registry's index url.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The machine name to use when adding a new entry.
+    pub fn machine(&self) -> &str {
+        &self.new_machine
+    }
+
+    /// Find the entry to use, returning the index of the file and of the entry within it.
+    ///
+    /// In order of preference, this is:
+    /// 1. an exact match, trying the machine names in order,
+    /// 2. the wildcard with the longest name, unless the machine name was given explicitly,
+    /// 3. a `default` entry.
+    ///
+    /// Entries with a `#cargo-path <prefix>` annotation only match registries whose index
+    /// url path starts with the prefix, and the longest matching prefix wins over shorter
+    /// ones and entries without the annotation. Likewise, entries with a
+    /// `#cargo-operation <names>` annotation only match the listed operations, and win over
+    /// entries without the annotation. Any remaining tie goes to the first entry.
+    ///
+    /// The first file with a matching entry is used, and the files after it aren't read.
+    pub fn find(
+        &self,
+        files: &mut NetrcFiles,
+    ) -> Result<Option<(usize, usize)>, cargo_credential::Error> {
+        self.find_for(files, self.operation)
+    }
+
+    /// Whether the entry to use depends on the operation, because of `#cargo-operation`
+    /// annotations.
+    ///
+    /// This may read more files than [`Lookup::find`]. If one of them can't be read, the
+    /// entry is assumed to depend on the operation.
+    pub fn depends_on_operation(&self, files: &mut NetrcFiles) -> bool {
+        let found: Vec<_> = OPERATIONS
+            .iter()
+            .map(|&operation| self.find_for(files, Some(operation)).ok())
+            .collect();
+        found
+            .iter()
+            .any(|found_for| found_for.is_none() || *found_for != found[0])
+    }
+
+    fn find_for(
+        &self,
+        files: &mut NetrcFiles,
+        operation: Option<&str>,
+    ) -> Result<Option<(usize, usize)>, cargo_credential::Error> {
+        let mut i = 0;
+        while let Some(file) = files.get(i)? {
+            if let Some(entry) = self.find_in(&file.netrc, operation) {
+                return Ok(Some((i, entry)));
+            }
+            i += 1;
+        }
+        Ok(None)
+    }
+
+    fn find_in(&self, netrc: &Netrc, operation: Option<&str>) -> Option<usize> {
+        self.machines
+            .iter()
+            .filter(|machine| {
+                self.bare_host || self.explicit || self.port.is_none() || **machine != self.host
+            })
+            .find_map(|machine| {
+                self.best(netrc, operation, |name| (name? == machine).then_some(()))
+            })
+            .or_else(|| {
+                self.best(netrc, operation, |name| {
+                    (self.wildcards && !self.explicit).then_some(())?;
+                    self.match_wildcard(name?)
+                })
+            })
+            .or_else(|| {
+                self.best(netrc, operation, |name| {
+                    (self.allow_default && name.is_none()).then_some(())
+                })
+            })
+    }
+
+    /// Find the most specific entry for the operation whose machine name is accepted by
+    /// `matches`.
+    ///
+    /// `matches` returns how specific the match is, which is compared before the length
+    /// of the matched `#cargo-path` prefix and whether the entry is specific to the
+    /// operation.
+    fn best<S: Ord>(
+        &self,
+        netrc: &Netrc,
+        operation: Option<&str>,
+        matches: impl Fn(Option<&str>) -> Option<S>,
+    ) -> Option<usize> {
+        let mut best = None;
+        for (entry, name) in netrc.entries() {
+            let Some(specificity) = matches(name) else {
+                continue;
+            };
+            let Some(path) = self.match_path(netrc.annotation(entry, "path")) else {
+                continue;
+            };
+            let Some(for_operation) =
+                match_operation(operation, netrc.annotation(entry, "operation"))
+            else {
+                continue;
+            };
+            let specificity = (specificity, path, for_operation);
+            if best.as_ref().is_none_or(|(best, _)| specificity > *best) {
+                best = Some((specificity, entry));
+            }
+        }
+        best.map(|(_, entry)| entry)
+    }
+
+    /// Match a `#cargo-path` prefix against the registry's index url path.
+    ///
+    /// Returns the length of the prefix, which is 0 for entries without one. The prefix has
+    /// to end at a `/`, so `/cargo/team-a` doesn't match `/cargo/team-ab/`.
+    fn match_path(&self, prefix: Option<&str>) -> Option<usize> {
+        let Some(prefix) = prefix else {
+            return Some(0);
+        };
+        let rest = self.path.strip_prefix(prefix)?;
+        (prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/')).then_some(prefix.len())
+    }
+
+    /// Match a wildcard machine name against the registry.
+    ///
+    /// Returns how specific the match is: the length of the matched suffix, and whether
+    /// the name has a port.
+    fn match_wildcard(&self, name: &str) -> Option<(usize, bool)> {
+        let pattern = name.strip_prefix('*').unwrap_or(name);
+        if !pattern.starts_with('.') {
+            return None;
+        }
+
+        let (suffix, port) = match pattern.rsplit_once(':') {
+            Some((suffix, port)) => (suffix, Some(port.parse::<u16>().ok()?)),
+            None => (pattern, None),
+        };
+        if port.is_some() && port != self.port {
+            return None;
+        }
+        if self.host.len() > suffix.len() && self.host.ends_with(suffix) {
+            Some((suffix.len(), port.is_some()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Match a `#cargo-operation` annotation, a list of operation names separated by commas or
+/// whitespace, against the operation.
+///
+/// Returns whether the entry is specific to the operation, which is `false` for entries
+/// without the annotation. Annotated entries never match when there is no operation, such
+/// as for `cargo login`.
+fn match_operation(operation: Option<&str>, names: Option<&str>) -> Option<bool> {
+    let Some(names) = names else {
+        return Some(false);
+    };
+    let operation = operation?;
+    names
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .any(|name| name == operation)
+        .then_some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::files::{ReadOptions, Source};
+
+    fn lookup() -> Lookup {
+        let registry: RegistryInfo<'_> =
+            serde_json::from_str(r#"{"index-url": "sparse+https://a.com/index/"}"#).unwrap();
+        Lookup::new(&registry, None).unwrap()
+    }
+
+    fn lookup_for(index_url: &str, name: Option<&str>, machine: Option<&str>) -> Lookup {
+        let registry = RegistryInfo {
+            index_url,
+            name,
+            headers: Vec::new(),
+        };
+        Lookup::new(&registry, machine).unwrap()
+    }
+
+    /// The login of the entry the lookup finds in `netrc`.
+    fn found(lookup: &Lookup, netrc: &str) -> Option<String> {
+        let netrc = Netrc::parse(netrc).unwrap();
+        let entry = lookup.find_in(&netrc, lookup.operation)?;
+        Some(netrc.get(entry, "login").unwrap().to_string())
+    }
+
+    #[test]
+    fn port() {
+        let mut lookup = lookup_for("sparse+https://a.com:8443/index/", None, None);
+        assert_eq!(lookup.machine(), "a.com:8443");
+        let both = "machine a.com login bare\nmachine a.com:8443 login port";
+        assert_eq!(found(&lookup, both).as_deref(), Some("port"));
+        let both = "machine a.com:8443 login port\nmachine a.com login bare";
+        assert_eq!(found(&lookup, both).as_deref(), Some("port"));
+        let bare = "machine a.com login bare\nmachine a.com:9000 login other";
+        assert_eq!(found(&lookup, bare).as_deref(), Some("bare"));
+
+        // As for login and logout.
+        lookup.bare_host = false;
+        assert_eq!(found(&lookup, both).as_deref(), Some("port"));
+        assert_eq!(found(&lookup, bare), None);
+
+        // Without a port, only the bare host matches.
+        let lookup = lookup_for("sparse+https://a.com/index/", None, None);
+        assert_eq!(lookup.machine(), "a.com");
+        assert_eq!(found(&lookup, "machine a.com:443 login port"), None);
+
+        let lookup = lookup_for("sparse+https://[::1]:8443/index/", None, None);
+        assert_eq!(lookup.machine(), "[::1]:8443");
+        let netrc = "machine ::1 login bare\nmachine [::1]:8443 login port";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("port"));
+    }
+
+    #[test]
+    fn wildcards() {
+        let mut lookup = lookup_for("sparse+https://b.a.example.com/index/", None, None);
+        let netrc = "machine *.example.com login wildcard";
+        assert_eq!(found(&lookup, netrc), None);
+        lookup.wildcards = true;
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("wildcard"));
+
+        let netrc = "machine *.example.com login wildcard\nmachine b.a.example.com login exact";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("exact"));
+        let netrc = "machine *.example.com login short\nmachine .a.example.com login long";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("long"));
+        let netrc = "machine *.example.com login first\nmachine .example.com login second";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("first"));
+        let netrc = "machine *.b.a.example.com login deeper\nmachine example.com login parent";
+        assert_eq!(found(&lookup, netrc), None);
+
+        // A wildcard only matches subdomains.
+        let mut lookup = lookup_for("sparse+https://example.com/index/", None, None);
+        lookup.wildcards = true;
+        assert_eq!(found(&lookup, "machine .example.com login wildcard"), None);
+        assert_eq!(
+            found(&lookup, "machine *.com login wildcard").as_deref(),
+            Some("wildcard")
+        );
+
+        // Wildcards with a port only match that port, and win over ones without.
+        let mut lookup = lookup_for("sparse+https://a.example.com:8443/index/", None, None);
+        lookup.wildcards = true;
+        let netrc = "machine *.example.com login any\nmachine *.example.com:8443 login port";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("port"));
+        let netrc = "machine *.example.com:9000 login other";
+        assert_eq!(found(&lookup, netrc), None);
+        let mut lookup = lookup_for("sparse+https://a.example.com/index/", None, None);
+        lookup.wildcards = true;
+        assert_eq!(
+            found(&lookup, "machine *.example.com:8443 login port"),
+            None
+        );
+
+        // An explicit machine name is only matched exactly.
+        let mut lookup = lookup_for("sparse+https://a.example.com/index/", None, Some("a.b.c"));
+        lookup.wildcards = true;
+        assert_eq!(found(&lookup, "machine *.example.com login wildcard"), None);
+        assert_eq!(found(&lookup, "machine *.b.c login wildcard"), None);
+    }
+
+    #[test]
+    fn paths() {
+        let lookup = lookup_for("sparse+https://a.com/cargo/team-a/index/", None, None);
+        let netrc = "machine a.com #cargo-path /cargo/\nlogin short\n\
+                     machine a.com #cargo-path /cargo/team-a\nlogin long\n\
+                     machine a.com #cargo-path /cargo/team-b/\nlogin other";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("long"));
+        let netrc = "machine a.com login plain\nmachine a.com #cargo-path /cargo/\nlogin annotated";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("annotated"));
+
+        // A prefix has to end at a `/`.
+        let lookup = lookup_for("sparse+https://a.com/cargo/team-ab/index/", None, None);
+        let netrc = "machine a.com #cargo-path /cargo/team-a\nlogin team-a";
+        assert_eq!(found(&lookup, netrc), None);
+        let netrc =
+            "machine a.com #cargo-path /cargo/team-a\nlogin team-a\nmachine a.com login plain";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("plain"));
+        let lookup = lookup_for("sparse+https://a.com/cargo/team-a", None, None);
+        let netrc = "machine a.com #cargo-path /cargo/team-a\nlogin team-a";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("team-a"));
+    }
+
+    #[test]
+    fn registry_names() {
+        let lookup = lookup_for("sparse+https://a.com:8443/index/", Some("example"), None);
+        // New entries are still added for the host.
+        assert_eq!(lookup.machine(), "a.com:8443");
+        let netrc = "machine a.com login bare\n\
+                     machine a.com:8443 login port\n\
+                     machine cargo:example login name\n\
+                     machine cargo:other login other";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("name"));
+        let lookup = lookup_for("sparse+https://a.com/index/", Some("other"), None);
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("other"));
+        let lookup = lookup_for("sparse+https://a.com/index/", None, None);
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("bare"));
+    }
+
+    #[test]
+    fn explicit_machine() {
+        let netrc = "machine a.com login bare\n\
+                     machine a.com:8443 login port\n\
+                     machine cargo:example login name\n\
+                     machine shared login shared";
+        let lookup = lookup_for(
+            "sparse+https://a.com:8443/index/",
+            Some("example"),
+            Some("shared"),
+        );
+        assert_eq!(lookup.machine(), "shared");
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("shared"));
+        // None of the derived names are used.
+        let lookup = lookup_for(
+            "sparse+https://a.com:8443/index/",
+            Some("example"),
+            Some("b.com"),
+        );
+        assert_eq!(found(&lookup, netrc), None);
+        // Even with a port, an explicit name that happens to be the host is used.
+        let mut lookup = lookup_for("sparse+https://a.com:8443/index/", None, Some("a.com"));
+        lookup.bare_host = false;
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("bare"));
+    }
+
+    #[test]
+    fn default_entry() {
+        let mut lookup = lookup_for("sparse+https://b.a.com/index/", None, None);
+        let netrc = "default login default";
+        assert_eq!(found(&lookup, netrc), None);
+        lookup.allow_default = true;
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("default"));
+
+        // Exact matches and wildcards win, wherever the default entry is.
+        lookup.wildcards = true;
+        let netrc = "machine b.a.com login exact\ndefault login default";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("exact"));
+        let netrc = "machine .a.com login wildcard\ndefault login default";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("wildcard"));
+        let netrc = "machine c.a.com login other\ndefault login default";
+        assert_eq!(found(&lookup, netrc).as_deref(), Some("default"));
+    }
+
+    fn netrc_files(dir: &Path, contents: &[&str]) -> NetrcFiles {
+        let sources = contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| {
+                let path = dir.join(format!("netrc{i}"));
+                fs::write(&path, content).unwrap();
+                Source {
+                    path,
+                    explicit: true,
+                }
+            })
+            .collect();
+        NetrcFiles::new(sources, ReadOptions::for_tests(false))
+    }
+
+    #[test]
+    fn later_files_are_only_read_when_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = netrc_files(dir.path(), &["machine a.com login a", "not a netrc file"]);
+        assert_eq!(lookup().find(&mut files).unwrap(), Some((0, 0)));
+
+        let mut files = netrc_files(dir.path(), &["machine b.com login b", "not a netrc file"]);
+        assert!(lookup().find(&mut files).is_err());
+    }
+
+    #[test]
+    fn first_file_with_a_match_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = netrc_files(
+            dir.path(),
+            &[
+                "machine b.com login b\nmachine a.com login first",
+                "machine a.com login second",
+            ],
+        );
+        let mut lookup = lookup();
+        assert_eq!(lookup.find(&mut files).unwrap(), Some((0, 1)));
+        assert!(!lookup.depends_on_operation(&mut files));
+
+        lookup.operation = Some("read");
+        let mut files = netrc_files(
+            dir.path(),
+            &[
+                "machine a.com login first\n#cargo-operation publish",
+                "machine a.com login second",
+            ],
+        );
+        assert_eq!(lookup.find(&mut files).unwrap(), Some((1, 0)));
+        assert!(lookup.depends_on_operation(&mut files));
+    }
+}
@@ -13,14 +13,162 @@
 //! the format of the token using the [handlebars templating language](https://handlebarsjs.com/)
 //! with the `--format` argument.
 //!
+//! Alternatively, pass `--preset <NAME>` to use a built-in format for a common registry.
+//! Run `cargo-credential-netrc presets` to list the presets along with their format and the
+//! variables they use.
+//!
+//! The `cloudsmith` and `gitlab` presets use HTTP basic authentication, the same as
+//! `basic`: use your username or the token's username as the login, and the API key or
+//! token as the password.
+//!
+//! Longer formats can be kept in a file and passed with `--format-file <PATH>`. The other
+//! `.hbs` files in the same directory can be included as partials, e.g. `{{> scope}}` includes
+//! `scope.hbs`. Only the partials the format uses are read, so other templates can live in
+//! the same directory. Trailing newlines are removed from the files.
+//!
 //! The following variables are available:
-//! - `login`
-//! - `account`
-//! - `password`
+//! - `login`, `account` and `password` from the .netrc entry,
+//! - `fields`, the other `key value` pairs of the .netrc entry, e.g. `{{fields.tenant}}` for an
+//!   entry with `tenant acme`,
+//! - `registry_name`, the name of the registry in `[registries.<name>]`, if known,
+//! - `index_url`, the index url of the registry,
+//! - `host`, `port` and `path` of the index url, where `port` is empty unless the url
+//!   has an explicit port,
+//! - `operation`, which is one of `read`, `publish`, `yank`, `unyank` or `owners`.
+//!
+//! For example, `{{login}}@{{host}}` or `{{#if port}}{{account}}{{else}}{{password}}{{/if}}`.
+//!
+//! The following helpers are also available:
+//! - `base64` and `base64url`, the standard and unpadded url-safe base64 encodings,
+//! - `urlencode`, which percent-encodes everything but unreserved characters,
+//! - `hex` and `sha256`, which both produce lowercase hex,
+//! - `trim`, `upper` and `lower`,
+//! - `concat`, which joins all of its arguments.
+//!
+//! For example, `Basic {{base64 (concat login ':' password)}}` builds a basic auth header.
+//! Use single quotes for string literals, since cargo can't pass double quotes to
+//! credential providers.
+//!
+//! Using an unknown variable is an error, and so is using a .netrc field that
+//! the entry doesn't have, rather than leaving it empty in the token. Use `{{#if account}}` to
+//! only use a field when it is present.
 //!
 //! *NOTE: If your token format requires a space, you MUST use a [credential alias](https://doc.rust-lang.org/cargo/reference/config.html#credential-alias)
 //! to specify the token format.*
 //!
+//! The following .netrc files are searched, in order, and the first matching entry is used:
+//! 1. the files given with `--netrc-file`, which can be repeated,
+//! 2. the file given by the `NETRC` environment variable,
+//! 3. `$HOME/.netrc`,
+//! 4. `$HOME/_netrc`,
+//! 5. `$HOME/.netrc.gpg`,
+//! 6. `$HOME/.netrc.age`.
+//!
+//! The files are read one at a time, and the files after the first one with a matching
+//! entry aren't read at all, so a missing, unreadable or encrypted file further down the
+//! list doesn't get in the way. Within a file, the most specific entry is used, as
+//! described below.
+//!
+//! Pass `--verbose` to see which file supplied the credentials. `cargo login` and
+//! `cargo logout` change the first file that has an entry for the registry. New entries
+//! are added to the first file given with `--netrc-file` or `NETRC`, or to the first
+//! home directory file that exists.
+//!
+//! On Unix, unencrypted files are refused if other users could read them or slip in
+//! their own credentials: the file must be owned by you and not be accessible to anyone
+//! else (`chmod 600`), and its directory must be owned by you or root and not be writable
+//! by others unless it is sticky. The directory may be readable by others, as home
+//! directories often are, since that only shows that the file exists. If the file is a
+//! symlink, the directory of the file it points to is checked as well. Pass
+//! `--insecure-permissions` to skip this check.
+//!
+//! Files ending in `.gpg` are decrypted with `gpg`, or the binary given with `--gpg <PATH>`.
+//! Files ending in `.age` are decrypted without any external binary, using the identity file
+//! given with `--age-identity <PATH>` or the `AGE_IDENTITY` environment variable. Either way,
+//! the decrypted content is only kept in memory, and `cargo login` and `cargo logout` can't
+//! change encrypted files.
+//!
+//! The contents of the .netrc files, the credentials read from them and the rendered
+//! token are wiped from memory once they are no longer needed, including the copies
+//! made along the way.
+//!
+//! Instead of a password, an entry can have a command that prints it, such as
+//! `passwordcmd "pass show registry/artifactory"`. Since this runs commands from the .netrc
+//! file, it is only done when passing `--allow-password-command`. The output is trimmed, and
+//! the command is killed if it takes longer than `--password-command-timeout` seconds, which
+//! defaults to 30. An entry's `password` always wins over its `passwordcmd`.
+//!
+//! Pass `--expand-env` to substitute `${VAR}` and `${VAR:-default}` in the `login`, `account`
+//! and `password` of an entry with environment variables, e.g. `password ${CARGO_REGISTRY_TOKEN}`
+//! in a .netrc file generated in CI. As in the shell, the default is used when the variable
+//! is unset or empty, and it is an error if a variable without a default isn't set.
+//!
+//! The credentials are taken from the `machine` entry for the host of the registry's index
+//! url. If the url has an explicit port, a `machine host:port` entry is preferred over one
+//! for the bare host, which lets registries on different ports of the same host use
+//! different credentials. `cargo login` and `cargo logout` never change the entry for the
+//! bare host in that case, since other registries and programs on the host may share it: a
+//! new `host:port` entry is added instead.
+//!
+//! To use different credentials for registries on the same host, or to not depend on the
+//! host at all, an entry can also be named after the registry: `machine cargo:<name>`,
+//! where `<name>` is the name of the registry in `[registries.<name>]`. Such an entry is
+//! preferred over entries for the host. Alternatively, pass `--machine <NAME>` to use the
+//! entry with that machine name instead of the host.
+//!
+//! Some hosts serve several registries under different paths, each with their own
+//! credentials. To support this, an entry can be annotated with a `#cargo-path` comment, and
+//! is then only used for registries whose index url path starts with the given prefix. The
+//! entry with the longest matching prefix wins:
+//!
+//! ```text
+//! machine artifactory.example.com #cargo-path /api/cargo/team-a/
+//!   login team-a
+//!   password <TEAM_A_PASSWORD>
+//!
+//! machine artifactory.example.com #cargo-path /api/cargo/team-b/
+//!   login team-b
+//!   password <TEAM_B_PASSWORD>
+//! ```
+//!
+//! To use different credentials for publishing than for downloading, annotate an entry with
+//! `#cargo-operation` followed by the operations it is for, out of `read`, `publish`, `yank`,
+//! `unyank` and `owners`. Such an entry wins over one without the annotation for those
+//! operations, and isn't used for any other:
+//!
+//! ```text
+//! machine registry.example.com
+//!   login reader
+//!   password <READ_TOKEN>
+//!
+//! machine registry.example.com #cargo-operation publish,yank,unyank,owners
+//!   login publisher
+//!   password <PUBLISH_TOKEN>
+//! ```
+//!
+//! Similarly, `--operation-format <OPERATION>=<FORMAT>` uses a different token format for an
+//! operation, e.g. `--operation-format 'publish=Bearer {{password}}'`. `cargo login` and
+//! `cargo logout` only change entries without a `#cargo-operation` annotation.
+//!
+//! Pass `--wildcards` to let `machine *.example.com` or `machine .example.com` entries match
+//! any subdomain of `example.com`. An exact match always wins over a wildcard, and the
+//! longest matching wildcard wins over shorter ones. `cargo login` and `cargo logout` only
+//! ever change exact matches.
+//!
+//! By default, cargo reuses the token for the rest of its invocation. Pass `--cache never` to
+//! have it ask every time, or `--cache <SECONDS>` to reuse the token for at most that long.
+//! Either way, a token is never reused past its expiry, which is taken from an `expires` field
+//! of the entry, e.g. `expires 2030-01-01T00:00:00Z` or a Unix timestamp. Pass `--jwt` to also
+//! take it from the `exp` claim if the password is a JWT. The JWT is only decoded, not verified.
+//!
+//! Expired credentials are refused rather than sent to the registry. Pass `--allow-expired`
+//! to send them anyway with a warning, e.g. when an `expires` field is out of date. Pass
+//! `--expiry-warning <SECONDS>` to be warned when they expire within that many seconds.
+//!
+//! If the registry doesn't have a `machine` entry in your .netrc file, the `default` entry
+//! can be used instead by passing `--allow-default`. This is off by default, since it would
+//! send the default credentials to every registry.
+//!
 //! ## Example
 //!
 //! Here is an example of how to format a token for JFrog's Artifactory:
@@ -33,16 +181,61 @@
 //! index = "sparse+<YOUR_ARTIFACTORY_URL>"
 //! credential-provider = "cargo-credential-artifactory"
 //! ```
+//!
+//! Or, using the built-in preset:
+//!
+//! ```toml
+//! [registries.artifactory]
+//! index = "sparse+<YOUR_ARTIFACTORY_URL>"
+//! credential-provider = ["cargo-credential-netrc", "--preset", "artifactory"]
+//! ```
+//!
+//! ## Login
+//!
+//! `cargo login` adds the registry to your .netrc file, or updates its entry if there
+//! already is one. The rest of the file is left untouched.
+//!
+//! If a token is given, the login, account and password are extracted from it using the
+//! token format. This only works for formats made of plain `{{variable}}` expressions
+//! that are separated by some text, such as `{{login}}:{{password}}`. Otherwise, you will
+//! be asked for each of the variables used by the format.
+//!
+//! ## Logout
+//!
+//! `cargo logout` removes the registry's entry from your .netrc file. Pass
+//! `--logout-password-only` to only remove the password and keep the rest of the entry.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
 
-use cargo_credential::{
-    Action, CacheControl, Credential, CredentialResponse, RegistryInfo, Secret,
-};
+use cargo_credential::{Action, Credential, CredentialResponse, Operation, RegistryInfo, Secret};
 use clap::Parser;
-use handlebars::Handlebars;
-use netrc::Netrc;
-use url::{Host, Url};
+use handlebars::RenderErrorReason;
+use zeroize::Zeroizing;
+
+use crate::decrypt::Decrypt;
+use crate::expiry::Cache;
+use crate::files::{NetrcFile, NetrcFiles, ReadOptions, Source};
+use crate::format::Format;
+use crate::lookup::Lookup;
+
+mod command;
+mod decrypt;
+mod env;
+mod expiry;
+mod files;
+mod format;
+mod lookup;
+mod memory;
+mod netrc;
+mod presets;
+
+#[global_allocator]
+static ALLOCATOR: memory::WipingAllocator = memory::WipingAllocator;
 
 /// Cargo credential provider that parses your .netrc file to get credentials.
 #[derive(Parser, Debug)]
@@ -54,15 +247,200 @@ struct Args {
     /// - login
     /// - account
     /// - password
+    /// - fields.<key>
+    /// - registry_name
+    /// - index_url
+    /// - host
+    /// - port
+    /// - path
+    /// - operation
     ///
     /// Examples:
     /// - `{{login}}:{{password}}`
     /// - `Bearer {{password}}`
-    #[arg(required = true)]
-    format: String,
+    #[arg(long, required_unless_present_any = ["preset", "format_file", "positional_format"])]
+    format: Option<String>,
+
+    /// The format as a positional argument, for compatibility with older versions.
+    #[arg(hide = true, conflicts_with_all = ["format", "preset", "format_file"])]
+    positional_format: Option<String>,
+
+    /// Use a built-in token format instead of `--format`.
+    ///
+    /// Run `cargo-credential-netrc presets` to list them.
+    #[arg(long, value_name = "NAME", conflicts_with = "format")]
+    preset: Option<String>,
+
+    /// Read the token format from a file instead of `--format`.
+    ///
+    /// The other `.hbs` files in the same directory can be used as partials, e.g.
+    /// `{{> header}}` includes `header.hbs`.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["format", "preset"])]
+    format_file: Option<PathBuf>,
+
+    /// Use a different token format for an operation. Can be given multiple times.
+    ///
+    /// The operation is one of `read`, `publish`, `yank`, `unyank` or `owners`, e.g.
+    /// `publish=Bearer {{password}}`.
+    #[arg(long, value_name = "OPERATION=FORMAT", value_parser = parse_operation_format)]
+    operation_format: Vec<(String, String)>,
+
+    /// Path of a .netrc file to search. Can be given multiple times.
+    ///
+    /// The files are searched in the order they are given, followed by the file in the `NETRC`
+    /// environment variable, `$HOME/.netrc`, `$HOME/_netrc`, `$HOME/.netrc.gpg` and
+    /// `$HOME/.netrc.age`. The first file with a matching entry wins. Files ending in `.gpg` are decrypted
+    /// with gpg, and files ending in `.age` with the `--age-identity`.
+    #[arg(long, value_name = "PATH")]
+    netrc_file: Vec<PathBuf>,
+
+    /// Look up the entry with this machine name instead of the registry's host.
+    ///
+    /// Without this, a `machine cargo:<registry name>` entry is preferred over the host,
+    /// where the registry name is the `<name>` of `[registries.<name>]` in the cargo config.
+    #[arg(long, value_name = "NAME")]
+    machine: Option<String>,
+
+    /// Use the `default` entry of the .netrc file for registries that don't have a `machine` entry.
+    ///
+    /// This is off by default, since it would send the default credentials to any registry.
+    #[arg(long)]
+    allow_default: bool,
+
+    /// Allow machine names to match subdomains of the registry's host.
+    ///
+    /// `machine *.example.com` and `machine .example.com` then match `a.example.com` and
+    /// `b.a.example.com`, but not `example.com`. An exact match always wins, and the longest
+    /// matching name wins among wildcards.
+    #[arg(long)]
+    wildcards: bool,
+
+    /// Only remove the password from the registry's entry on logout, rather than the whole entry.
+    #[arg(long)]
+    logout_password_only: bool,
+
+    /// The gpg binary used to decrypt `.gpg` files.
+    #[arg(long, value_name = "PATH", default_value = "gpg")]
+    gpg: PathBuf,
+
+    /// The age identity file used to decrypt `.age` files.
+    ///
+    /// Defaults to the `AGE_IDENTITY` environment variable.
+    #[arg(long, value_name = "PATH")]
+    age_identity: Option<PathBuf>,
+
+    /// Run the `passwordcmd` of an entry that has no password, and use its output as the password.
+    ///
+    /// This is off by default, since it runs commands from the .netrc file.
+    #[arg(long)]
+    allow_password_command: bool,
+
+    /// How long to wait for a `passwordcmd` to finish, in seconds.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    password_command_timeout: u64,
+
+    /// Expand `${VAR}` and `${VAR:-default}` in the login, account and password of the entry.
+    ///
+    /// It is an error if a variable without a default isn't set.
+    #[arg(long)]
+    expand_env: bool,
+
+    /// Read .netrc files that other users can access.
+    ///
+    /// By default, a file is refused on Unix if it isn't owned by the current user, if other
+    /// users can read or change it, or if other users can replace it through its directory.
+    #[arg(long)]
+    insecure_permissions: bool,
+
+    /// How long cargo may reuse the token: `never`, `session` or a number of seconds.
+    ///
+    /// The token is never cached past its expiry, which is taken from the entry's `expires`
+    /// field, or the `exp` claim of a JWT password with `--jwt`.
+    #[arg(long, value_name = "never|session|SECONDS", default_value = "session")]
+    cache: Cache,
+
+    /// Warn on stderr when the credentials expire within this many seconds.
+    ///
+    /// Expired credentials are refused unless `--allow-expired` is passed. See `--cache` for
+    /// how the expiry is found.
+    #[arg(long, value_name = "SECONDS")]
+    expiry_warning: Option<u64>,
+
+    /// Send expired credentials to the registry anyway, with a warning.
+    ///
+    /// Useful when the expiry is wrong, e.g. an `expires` field that is out of date.
+    #[arg(long)]
+    allow_expired: bool,
+
+    /// Take the expiry of JWT passwords from their `exp` claim.
+    ///
+    /// Other passwords are left alone. The JWT is only decoded, not verified.
+    #[arg(long)]
+    jwt: bool,
+
+    /// Print which .netrc files are read and which one supplied the credentials to stderr.
+    #[arg(short, long)]
+    verbose: bool,
 }
 
-struct NetrcCredential;
+impl Args {
+    /// Get how to read the .netrc files.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            decrypt: Decrypt {
+                gpg: self.gpg.clone(),
+                age_identity: self.age_identity.clone().or_else(|| {
+                    std::env::var_os("AGE_IDENTITY")
+                        .filter(|path| !path.is_empty())
+                        .map(PathBuf::from)
+                }),
+            },
+            check_permissions: !self.insecure_permissions,
+            verbose: self.verbose,
+        }
+    }
+}
+
+#[derive(Default)]
+struct NetrcCredential {
+    /// The formats read with `--format-file`, which are only compiled once per process.
+    format_files: RefCell<HashMap<PathBuf, Rc<Format>>>,
+}
+
+impl NetrcCredential {
+    /// Get the token format, which may come from a preset or a file.
+    ///
+    /// The format is checked for unknown variables up front, rather than rendering them as
+    /// empty strings.
+    fn format(&self, args: &Args) -> Result<Rc<Format>, cargo_credential::Error> {
+        if let Some(path) = &args.format_file {
+            let mut format_files = self.format_files.borrow_mut();
+            if let Some(format) = format_files.get(path) {
+                return Ok(format.clone());
+            }
+            let format = Rc::new(Format::from_file(path)?);
+            format_files.insert(path.clone(), format.clone());
+            return Ok(format);
+        }
+
+        let source = match &args.preset {
+            Some(name) => presets::find(name)
+                .map(|preset| preset.format)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown preset `{name}`, run `cargo-credential-netrc presets` to list them"
+                    )
+                })?,
+            // Clap makes sure one of them is present.
+            None => args
+                .format
+                .as_deref()
+                .or(args.positional_format.as_deref())
+                .unwrap_or_default(),
+        };
+        Ok(Rc::new(Format::new(source)?))
+    }
+}
 
 impl Credential for NetrcCredential {
     fn perform(
@@ -73,53 +451,654 @@ impl Credential for NetrcCredential {
     ) -> Result<CredentialResponse, cargo_credential::Error> {
         let args =
             Args::try_parse_from(args).map_err(|e| cargo_credential::Error::Other(Box::new(e)))?;
+        let format = self.format(&args)?;
 
         match action {
-            Action::Get(_) => {
-                // Parse the url to get the host.
-                let host = match Url::parse(registry.index_url)
-                    .map_err(|e| cargo_credential::Error::Other(Box::new(e)))?
-                    .host()
+            Action::Get(operation) => {
+                let operation = operation_name(operation);
+                let mut lookup = Lookup::new(registry, args.machine.as_deref())?;
+                lookup.allow_default = args.allow_default;
+                lookup.wildcards = args.wildcards;
+                lookup.operation = Some(operation);
+
+                // The last format given for the operation replaces the usual one.
+                let format = match args
+                    .operation_format
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == operation)
                 {
-                    Some(Host::Domain(host)) => host.to_string(),
-                    Some(Host::Ipv4(ip)) => ip.to_string(),
-                    Some(Host::Ipv6(ip)) => ip.to_string(),
-                    _ => return Err(cargo_credential::Error::UrlNotSupported),
+                    Some((_, source)) => Rc::new(Format::new(source)?),
+                    None => format,
                 };
 
-                // Parse the .netrc file.
-                let netrc =
-                    Netrc::new().map_err(|e| cargo_credential::Error::Other(Box::new(e)))?;
+                // Parse the .netrc files.
+                let mut files =
+                    NetrcFiles::new(files::sources(&args.netrc_file)?, args.read_options());
+
+                match lookup.find(&mut files)? {
+                    Some((file, entry)) => {
+                        let NetrcFile { path, netrc } = &files[file];
+                        let described = format!(
+                            "the entry for {} in {}",
+                            netrc.name(entry).unwrap_or("default"),
+                            path.display()
+                        );
+                        if args.verbose {
+                            eprintln!("using {described}");
+                        }
 
-                match netrc.hosts.get(&host) {
-                    Some(authenticator) => {
-                        let handlebars = Handlebars::new();
+                        // Fields the entry doesn't have are left out, so that using them
+                        // is an error. If a field is repeated, the first one wins.
+                        let mut data = format::Data::default();
+                        for name in format::VARIABLES {
+                            if let Some(value) = netrc.get(entry, name) {
+                                let value = if args.expand_env {
+                                    env::expand(value).map_err(|e| {
+                                        format!("{e}, for the `{name}` of {described}")
+                                    })?
+                                } else {
+                                    Zeroizing::new(value.to_string())
+                                };
+                                data.variables.insert(name, Secret::from(value));
+                            }
+                        }
+                        // An entry without a password can get it from a command instead.
+                        if let Some(command) = netrc.get(entry, "passwordcmd").filter(|_| {
+                            args.allow_password_command && !data.variables.contains_key("password")
+                        }) {
+                            let timeout = Duration::from_secs(args.password_command_timeout);
+                            let password = command::run(command, timeout).map_err(|e| {
+                                format!("{e}, for the `passwordcmd` of {described}")
+                            })?;
+                            data.variables.insert("password", Secret::from(password));
+                        }
+                        for (key, value) in netrc.fields(entry) {
+                            if !format::VARIABLES.contains(&key) {
+                                data.fields.entry(key).or_insert_with(|| {
+                                    Secret::from(Zeroizing::new(value.to_string()))
+                                });
+                            }
+                        }
+                        let context: [(&str, String); 6] = [
+                            (
+                                "registry_name",
+                                registry.name.unwrap_or_default().to_string(),
+                            ),
+                            ("index_url", registry.index_url.to_string()),
+                            ("host", lookup.host().to_string()),
+                            (
+                                "port",
+                                lookup
+                                    .port()
+                                    .map(|port| port.to_string())
+                                    .unwrap_or_default(),
+                            ),
+                            ("path", lookup.path().to_string()),
+                            ("operation", operation.to_string()),
+                        ];
+                        for (name, value) in context {
+                            data.variables
+                                .insert(name, Secret::from(Zeroizing::new(value)));
+                        }
 
-                        let mut data = HashMap::new();
-                        data.insert("login", Secret::from(authenticator.login.clone()));
-                        data.insert("account", Secret::from(authenticator.account.clone()));
-                        data.insert("password", Secret::from(authenticator.password.clone()));
+                        let mut token = format
+                            .render(&data)
+                            .map_err(|e| match e.reason() {
+                                RenderErrorReason::MissingVariable(Some(name))
+                                    if name == "password"
+                                        && netrc.get(entry, "passwordcmd").is_some() =>
+                                {
+                                    format!(
+                                        "{described} only has a `passwordcmd`, pass `--allow-password-command` to run it"
+                                    )
+                                    .into()
+                                }
+                                RenderErrorReason::MissingVariable(Some(name)) => format!(
+                                    "the token format uses `{name}`, but {described} doesn't have it"
+                                )
+                                .into(),
+                                _ => cargo_credential::Error::Other(Box::new(e)),
+                            })?;
 
-                        let token: Secret<String> = handlebars
-                            .render_template(&args.format, &data)
-                            .map_err(|e| cargo_credential::Error::Other(Box::new(e)))?
-                            .into();
+                        let expiration = expiry::expiration(
+                            netrc.get(entry, "expires"),
+                            data.variables
+                                .get("password")
+                                .filter(|_| args.jwt)
+                                .map(|password| password.as_deref().expose().as_str()),
+                        )
+                        .map_err(|e| format!("{e} in {described}"))?;
+                        if let Some(expiration) = &expiration {
+                            expiration.check(
+                                args.expiry_warning,
+                                args.allow_expired,
+                                &described,
+                            )?;
+                        }
 
                         Ok(CredentialResponse::Get {
-                            token,
-                            cache: CacheControl::Session,
-                            operation_independent: true,
+                            // cargo-credential owns the token from here on, and the allocator
+                            // wipes it once the response has been sent.
+                            token: Secret::from(std::mem::take(&mut *token)),
+                            cache: args
+                                .cache
+                                .control(expiration.map(|expiration| expiration.time)),
+                            // A token that depends on the operation, through the format or the
+                            // entry, can't be reused for other ones.
+                            operation_independent: !format.uses_operation()
+                                && args.operation_format.is_empty()
+                                && !lookup.depends_on_operation(&mut files),
                         })
                     }
                     None => Err(cargo_credential::Error::NotFound),
                 }
             }
+            Action::Login(options) => {
+                // The entry for the bare host may be shared with other registries on the
+                // host, so it is left alone.
+                let mut lookup = Lookup::new(registry, args.machine.as_deref())?;
+                lookup.bare_host = false;
+
+                // Work out the netrc fields, either from the token cargo gave us or by
+                // asking the user for each of them.
+                let fields = match &options.token {
+                    Some(token) => format::parse_token(format.source(), token.as_deref().expose())
+                        .ok_or_else(|| {
+                            format!(
+                                "unable to extract the netrc fields from the token using the format `{}`",
+                                format.source()
+                            )
+                        })?,
+                    None => prompt_fields(format.source(), lookup.machine())?,
+                };
+
+                // Update the first file that has an entry for the registry. Otherwise, add
+                // one to the first explicitly configured or existing file.
+                let sources = files::sources(&args.netrc_file)?;
+                // Explicitly configured files don't have to exist yet, they are created.
+                let existing = sources
+                    .iter()
+                    .map(|source| Source {
+                        explicit: false,
+                        ..source.clone()
+                    })
+                    .collect();
+                let mut files = NetrcFiles::new(existing, args.read_options());
+                let (path, netrc) = match lookup.find(&mut files)? {
+                    Some((file, entry)) => {
+                        let NetrcFile { path, mut netrc } = files.take(file);
+                        for (key, value) in &fields {
+                            netrc.set(entry, key, value);
+                        }
+                        (path, netrc)
+                    }
+                    None => {
+                        let source = sources
+                            .iter()
+                            .find(|source| source.explicit || source.path.exists())
+                            .unwrap_or(&sources[0]);
+                        let mut netrc = files::read(&source.path, false, &args.read_options())?
+                            .unwrap_or_default();
+                        let fields: Vec<(&str, &str)> = fields
+                            .iter()
+                            .map(|(key, value)| (*key, value.as_str()))
+                            .collect();
+                        netrc.add_machine(lookup.machine(), &fields);
+                        (source.path.clone(), netrc)
+                    }
+                };
+                if args.verbose {
+                    eprintln!(
+                        "writing credentials for {} to {}",
+                        lookup.machine(),
+                        path.display()
+                    );
+                }
+                files::write(&path, &netrc)?;
+
+                Ok(CredentialResponse::Login)
+            }
+            Action::Logout => {
+                let mut lookup = Lookup::new(registry, args.machine.as_deref())?;
+                lookup.bare_host = false;
+
+                // Only the entry that would be used to get the credentials is changed.
+                let mut files =
+                    NetrcFiles::new(files::sources(&args.netrc_file)?, args.read_options());
+                let (file, entry) = lookup
+                    .find(&mut files)?
+                    .ok_or(cargo_credential::Error::NotFound)?;
+                let NetrcFile { path, mut netrc } = files.take(file);
+                if args.logout_password_only {
+                    if !netrc.remove_field(entry, "password") {
+                        return Err(cargo_credential::Error::NotFound);
+                    }
+                } else {
+                    netrc.remove(entry);
+                }
+                if args.verbose {
+                    eprintln!(
+                        "removing credentials for {} from {}",
+                        lookup.machine(),
+                        path.display()
+                    );
+                }
+                files::write(&path, &netrc)?;
+
+                Ok(CredentialResponse::Logout)
+            }
             // If a credential provider doesn't support a given operation, it should respond with `OperationNotSupported`.
             _ => Err(cargo_credential::Error::OperationNotSupported),
         }
     }
 }
 
+/// Parse an `--operation-format` argument, checking the format up front.
+fn parse_operation_format(arg: &str) -> Result<(String, String), String> {
+    let (operation, format) = arg
+        .split_once('=')
+        .ok_or("expected the operation and the format separated by `=`")?;
+    if !lookup::OPERATIONS.contains(&operation) {
+        return Err(format!(
+            "unknown operation `{operation}`, expected one of {}",
+            lookup::OPERATIONS.join(", ")
+        ));
+    }
+    Format::new(format)?;
+    Ok((operation.to_string(), format.to_string()))
+}
+
+/// Get the name of the operation, as exposed to the token format.
+fn operation_name(operation: &Operation<'_>) -> &'static str {
+    match operation {
+        Operation::Read => "read",
+        Operation::Publish { .. } => "publish",
+        Operation::Yank { .. } => "yank",
+        Operation::Unyank { .. } => "unyank",
+        Operation::Owners { .. } => "owners",
+        _ => "unknown",
+    }
+}
+
+/// Ask the user for each variable used by the format.
+///
+/// Falls back to asking for the login and password if the format can't be reversed.
+fn prompt_fields(
+    format: &str,
+    host: &str,
+) -> Result<Vec<(&'static str, Zeroizing<String>)>, cargo_credential::Error> {
+    let variables = format::variables(format).unwrap_or_else(|| vec!["login", "password"]);
+
+    let mut fields = Vec::new();
+    for name in format::VARIABLES {
+        if !variables.contains(&name) {
+            continue;
+        }
+        eprint!("{name} for {host}: ");
+        io::stderr().flush().map_err(Box::new)?;
+        let value = cargo_credential::read_line().map_err(Box::new)?;
+        fields.push((name, Zeroizing::new(value)));
+    }
+    Ok(fields)
+}
+
 fn main() {
-    cargo_credential::main(NetrcCredential);
+    if std::env::args().nth(1).as_deref() == Some("presets") {
+        presets::print();
+        return;
+    }
+    cargo_credential::main(NetrcCredential::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    /// Keep the tests away from the user's .netrc files.
+    fn isolate() {
+        static HOME: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+        HOME.get_or_init(|| {
+            let home = tempfile::tempdir().unwrap();
+            std::env::set_var("HOME", home.path());
+            std::env::remove_var("NETRC");
+            home
+        });
+    }
+
+    /// Write a .netrc file that only the current user can access.
+    fn netrc_file(dir: &Path, content: &str) -> PathBuf {
+        let path = dir.join(".netrc");
+        fs::write(&path, content).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        path
+    }
+
+    /// Send a request for `https://registry.example.com/index/`, which cargo calls `example`,
+    /// with the .netrc file at `path`. `action` is the JSON of the action, without braces.
+    fn perform(
+        path: &Path,
+        action: &str,
+        args: &[&str],
+    ) -> Result<CredentialResponse, cargo_credential::Error> {
+        perform_for(
+            "sparse+https://registry.example.com/index/",
+            path,
+            action,
+            args,
+        )
+    }
+
+    fn perform_for(
+        index_url: &str,
+        path: &Path,
+        action: &str,
+        args: &[&str],
+    ) -> Result<CredentialResponse, cargo_credential::Error> {
+        isolate();
+        let request = format!(
+            r#"{{"v": 1, "registry": {{"index-url": "{index_url}", "name": "example"}}, {action}}}"#
+        );
+        let request: cargo_credential::CredentialRequest<'_> =
+            serde_json::from_str(&request).unwrap();
+        let path = path.to_str().unwrap();
+        let args: Vec<&str> = ["cargo-credential-netrc", "--netrc-file", path]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        NetrcCredential::default().perform(&request.registry, &request.action, &args)
+    }
+
+    const GET: &str = r#""kind": "get", "operation": "read""#;
+
+    /// Get the token, or the error message.
+    fn token(path: &Path, args: &[&str]) -> Result<String, String> {
+        match perform(path, GET, args) {
+            Ok(CredentialResponse::Get { token, .. }) => Ok(token.expose()),
+            Ok(response) => panic!("unexpected response {response:?}"),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    const LOGOUT: &str = r#""kind": "logout""#;
+    const BASIC: [&str; 2] = ["--format", "{{login}}:{{password}}"];
+
+    /// Log in with `token`, returning the .netrc file afterwards or the error message.
+    fn login(path: &Path, token: &str, format: &str) -> Result<String, String> {
+        let action = format!(r#""kind": "login", "token": "{token}""#);
+        match perform(path, &action, &["--format", format]) {
+            Ok(CredentialResponse::Login) => Ok(fs::read_to_string(path).unwrap()),
+            Ok(response) => panic!("unexpected response {response:?}"),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    #[test]
+    fn login_updates_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(
+            dir.path(),
+            "# work\nmachine registry.example.com login old password old\nmachine other.com\n",
+        );
+        assert_eq!(
+            login(&path, "user:pass", "{{login}}:{{password}}"),
+            Ok("# work\nmachine registry.example.com login user password pass\nmachine other.com\n"
+                .to_string())
+        );
+        // Only the fields in the format are changed.
+        assert_eq!(
+            login(&path, "Bearer token", "Bearer {{password}}"),
+            Ok("# work\nmachine registry.example.com login user password token\nmachine other.com\n"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn login_adds_an_entry_before_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(dir.path(), "machine other.com login o\ndefault login d\n");
+        assert_eq!(
+            login(&path, "user:pass", "{{login}}:{{password}}"),
+            Ok("machine other.com login o\n\
+                machine registry.example.com login user password pass\n\
+                default login d\n"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn login_creates_a_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("netrc");
+        assert_eq!(
+            login(&path, "Bearer token", "Bearer {{password}}"),
+            Ok("machine registry.example.com password token\n".to_string())
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn login_with_a_format_that_cant_be_reversed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(dir.path(), "");
+        let format = "Basic {{base64 (concat login ':' password)}}";
+        assert_eq!(
+            login(&path, "Basic dXNlcjpwYXNz", format),
+            Err(format!(
+                "unable to extract the netrc fields from the token using the format `{format}`"
+            ))
+        );
+        // The token doesn't match the format.
+        assert!(login(&path, "Token abc", "Bearer {{password}}").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    /// Log out, returning the .netrc file afterwards.
+    fn logout(path: &Path, args: &[&str]) -> Result<String, cargo_credential::Error> {
+        let args: Vec<&str> = BASIC.iter().chain(args).copied().collect();
+        match perform(path, LOGOUT, &args)? {
+            CredentialResponse::Logout => Ok(fs::read_to_string(path).unwrap()),
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn logout_removes_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(
+            dir.path(),
+            "machine other.com login o\n\n\
+             machine registry.example.com\n  login user\n  password pass\n\n\
+             default login d\n",
+        );
+        assert_eq!(
+            logout(&path, &[]).unwrap(),
+            "machine other.com login o\n\ndefault login d\n"
+        );
+        // The default entry isn't used without --allow-default.
+        assert!(matches!(
+            logout(&path, &[]),
+            Err(cargo_credential::Error::NotFound)
+        ));
+    }
+
+    #[test]
+    fn logout_removes_the_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(
+            dir.path(),
+            "machine registry.example.com login user password pass account a\n",
+        );
+        assert_eq!(
+            logout(&path, &["--logout-password-only"]).unwrap(),
+            "machine registry.example.com login user account a\n"
+        );
+        // There is no password left to remove.
+        assert!(matches!(
+            logout(&path, &["--logout-password-only"]),
+            Err(cargo_credential::Error::NotFound)
+        ));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "machine registry.example.com login user account a\n"
+        );
+    }
+
+    #[test]
+    fn login_and_logout_leave_the_bare_host_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = "machine registry.example.com login shared password secret\n";
+        let path = netrc_file(dir.path(), shared);
+        let url = "sparse+https://registry.example.com:8443/index/";
+
+        // Getting the credentials falls back to the bare host.
+        match perform_for(url, &path, GET, &BASIC) {
+            Ok(CredentialResponse::Get { token, .. }) => {
+                assert_eq!(token.expose(), "shared:secret")
+            }
+            response => panic!("unexpected response {response:?}"),
+        }
+        assert!(matches!(
+            perform_for(url, &path, LOGOUT, &BASIC),
+            Err(cargo_credential::Error::NotFound)
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), shared);
+
+        let login = r#""kind": "login", "token": "user:pass""#;
+        perform_for(url, &path, login, &BASIC).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{shared}machine registry.example.com:8443 login user password pass\n")
+        );
+        perform_for(url, &path, LOGOUT, &BASIC).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), shared);
+    }
+
+    #[test]
+    fn registry_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(
+            dir.path(),
+            "machine registry.example.com login user password pass\n\
+             machine registry.example.com:8443 login user password pass\n",
+        );
+        let format = "{{registry_name}} {{index_url}} {{host}} [{{port}}] {{path}} {{operation}}";
+        let publish = r#""kind": "get", "operation": "publish", "name": "foo", "vers": "1.0.0", "cksum": "abc""#;
+
+        let token = |url, action| match perform_for(url, &path, action, &["--format", format]) {
+            Ok(CredentialResponse::Get {
+                token,
+                operation_independent,
+                ..
+            }) => {
+                assert!(!operation_independent);
+                token.expose()
+            }
+            response => panic!("unexpected response {response:?}"),
+        };
+        assert_eq!(
+            token("sparse+https://registry.example.com/index/", GET),
+            "example sparse+https://registry.example.com/index/ registry.example.com [] /index/ read"
+        );
+        assert_eq!(
+            token("sparse+https://registry.example.com:8443/cargo/", publish),
+            "example sparse+https://registry.example.com:8443/cargo/ registry.example.com [8443] \
+             /cargo/ publish"
+        );
+    }
+
+    #[test]
+    fn custom_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = netrc_file(
+            dir.path(),
+            "machine registry.example.com login user password pass tenant acme \
+             scope publish tenant ignored\n",
+        );
+        assert_eq!(
+            token(
+                &path,
+                &[
+                    "--format",
+                    "{{fields.tenant}}/{{fields.scope}}:{{password}}"
+                ]
+            ),
+            Ok("acme/publish:pass".to_string())
+        );
+        assert_eq!(
+            token(&path, &["--format", "{{fields.region}}"]),
+            Err(format!(
+                "the token format uses `fields.region`, but the entry for registry.example.com \
+                 in {} doesn't have it",
+                path.display()
+            ))
+        );
+    }
+
+    #[test]
+    fn jwt_expiry_is_opt_in() {
+        let dir = tempfile::tempdir().unwrap();
+        // {"exp": 1000000000}, which is in 2001.
+        let jwt = "e30.eyJleHAiOjEwMDAwMDAwMDB9.c2ln";
+        let path = netrc_file(
+            dir.path(),
+            &format!("machine registry.example.com password {jwt}\n"),
+        );
+
+        assert_eq!(
+            token(&path, &["--format", "Bearer {{password}}"]),
+            Ok(format!("Bearer {jwt}"))
+        );
+        let error = token(&path, &["--jwt", "--format", "Bearer {{password}}"]).unwrap_err();
+        assert!(
+            error.contains("is a JWT that expired at 2001-09-09T01:46:40Z"),
+            "{error}"
+        );
+        assert_eq!(
+            token(
+                &path,
+                &[
+                    "--jwt",
+                    "--allow-expired",
+                    "--format",
+                    "Bearer {{password}}"
+                ]
+            ),
+            Ok(format!("Bearer {jwt}"))
+        );
+    }
+
+    #[test]
+    fn format_files_are_compiled_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.hbs");
+        fs::write(&path, "Bearer {{password}}\n").unwrap();
+        let args = Args::try_parse_from([
+            Path::new("cargo-credential-netrc"),
+            Path::new("--format-file"),
+            &path,
+        ])
+        .unwrap();
+
+        let credential = NetrcCredential::default();
+        let format = credential.format(&args).unwrap();
+        assert_eq!(format.source(), "Bearer {{password}}");
+        // Later requests in the same process don't read the file again.
+        fs::remove_file(&path).unwrap();
+        assert!(Rc::ptr_eq(&format, &credential.format(&args).unwrap()));
+        assert!(NetrcCredential::default().format(&args).is_err());
+    }
 }
@@ -0,0 +1,36 @@
+//! Wiping freed memory, so that secrets don't linger on the heap.
+//!
+//! The crate keeps the secrets it owns in [`Zeroizing`](zeroize::Zeroizing) buffers, but
+//! copies are also made where it can't wrap them: when a string grows and is moved to a
+//! larger allocation, inside handlebars and serde_json while rendering, and by
+//! cargo-credential, which owns the response once it is returned. [`WipingAllocator`] wipes
+//! every allocation when it is freed, which covers those copies too.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::mem::MaybeUninit;
+
+use zeroize::Zeroize;
+
+/// The system allocator, except that memory is wiped before it is freed.
+pub struct WipingAllocator;
+
+unsafe impl GlobalAlloc for WipingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: the caller guarantees that `ptr` is a live block of `layout.size()` bytes
+        // that nothing else refers to. Parts of it, like spare `Vec` capacity or padding, may
+        // be uninitialized, so it is wiped as `MaybeUninit<u8>` rather than `u8`.
+        std::slice::from_raw_parts_mut(ptr.cast::<MaybeUninit<u8>>(), layout.size()).zeroize();
+        System.dealloc(ptr, layout);
+    }
+
+    // `realloc` isn't overridden: the default allocates a new block, copies the data and
+    // frees the old block through `dealloc`, so it is wiped as well.
+}
@@ -0,0 +1,846 @@
+//! Lossless parsing and editing of .netrc files.
+//!
+//! [`Netrc`] keeps every byte of the original file as a sequence of tokens: whitespace,
+//! comments, keywords, values and `macdef` bodies. Writing it back with
+//! [`Display`](fmt::Display) reproduces the file exactly, and an edit only touches the
+//! tokens of the entry being changed, so hand-maintained files keep their layout.
+//!
+//! The syntax follows the netrc parser of the Python standard library, which is also what
+//! `rust-netrc` implements:
+//! - Tokens are separated by whitespace. Values can be quoted with `"` and `\` escapes
+//!   the next character.
+//! - `#` starts a comment that runs to the end of the line, except where a value is
+//!   expected.
+//! - `machine <name>` and `default` start an entry, which is followed by `login` (or
+//!   `user`), `account` and `password` fields in any order.
+//! - `macdef <name>` defines a macro whose body runs until the first blank line.
+//!
+//! On top of that, any other `key value` pair inside an entry is accepted as a custom
+//! field, such as `tenant acme`, rather than being an error. Comments of the form
+//! `#cargo-<name> <value>` inside an entry are treated as annotations of that entry, see
+//! [`Netrc::annotation`].
+
+use std::fmt;
+
+use zeroize::Zeroize;
+
+/// Error produced when a .netrc file cannot be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parsing error: {} (line {})", self.message, self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    /// Whitespace, including newlines.
+    Space,
+    /// A `#` comment, up to but excluding the end of the line.
+    Comment,
+    /// A keyword such as `machine`, `login` or `macdef`.
+    Keyword(String),
+    /// The (unescaped) value following a keyword.
+    Value(String),
+    /// The body of a `macdef`, up to but excluding the terminating blank line.
+    Macro,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    raw: String,
+}
+
+impl Drop for Token {
+    // Values, and the raw text of any token, can be secrets.
+    fn drop(&mut self) {
+        self.raw.zeroize();
+        if let Kind::Value(value) = &mut self.kind {
+            value.zeroize();
+        }
+    }
+}
+
+/// A `key value` pair inside an entry.
+#[derive(Debug, Clone)]
+struct Field {
+    /// The normalized key (`user` is stored as `login`).
+    key: String,
+    /// Index of the key token.
+    start: usize,
+    /// Index of the value token.
+    value: usize,
+}
+
+/// A `machine` or `default` entry.
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The machine name, or `None` for the `default` entry.
+    name: Option<String>,
+    /// Index of the `machine` or `default` keyword token.
+    start: usize,
+    /// Index one past the machine name, or past the `default` keyword.
+    header_end: usize,
+    /// Index one past the last token of the entry.
+    end: usize,
+    fields: Vec<Field>,
+    /// Indices of the annotation comments in the entry, see [`Netrc::annotation`].
+    annotations: Vec<usize>,
+}
+
+/// A .netrc file that can be edited and written back without reformatting it.
+#[derive(Debug, Clone, Default)]
+pub struct Netrc {
+    tokens: Vec<Token>,
+    entries: Vec<Entry>,
+}
+
+impl Netrc {
+    /// Parse the contents of a .netrc file.
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let tokens = Lexer::new(src).tokenize()?;
+        let mut file = Netrc {
+            tokens,
+            entries: Vec::new(),
+        };
+        file.reindex();
+        Ok(file)
+    }
+
+    /// Iterate over the entries, yielding their index and machine name, which is `None` for
+    /// the `default` entry.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, Option<&str>)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (i, entry.name.as_deref()))
+    }
+
+    /// Get the machine name of the given entry, or `None` for the `default` entry.
+    pub fn name(&self, entry: usize) -> Option<&str> {
+        self.entries[entry].name.as_deref()
+    }
+
+    /// Get the value of `key` in the given entry.
+    pub fn get(&self, entry: usize, key: &str) -> Option<&str> {
+        self.fields(entry)
+            .find(|(field, _)| *field == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over the fields of the given entry in order, yielding their key and value.
+    ///
+    /// This includes custom fields, and a key appears more than once if the entry repeats it.
+    pub fn fields(&self, entry: usize) -> impl Iterator<Item = (&str, &str)> {
+        self.entries[entry]
+            .fields
+            .iter()
+            .map(|field| match &self.tokens[field.value].kind {
+                Kind::Value(value) => (field.key.as_str(), value.as_str()),
+                _ => unreachable!("fields always point to a value token"),
+            })
+    }
+
+    /// Get the value of an annotation in the given entry.
+    ///
+    /// Annotations are comments of the form `#cargo-<name> <value>` that follow the
+    /// `machine` or `default` keyword, up to the next entry. They attach extra information
+    /// to an entry without affecting other programs that read the file.
+    pub fn annotation(&self, entry: usize, name: &str) -> Option<&str> {
+        self.entries[entry].annotations.iter().find_map(|&i| {
+            match parse_annotation(&self.tokens[i].raw) {
+                Some((key, value)) if key == name => Some(value),
+                _ => None,
+            }
+        })
+    }
+
+    /// Set `key` to `value` in the given entry, adding the field if it is missing.
+    pub fn set(&mut self, entry: usize, key: &str, value: &str) {
+        let value_token = Token {
+            kind: Kind::Value(value.to_string()),
+            raw: quote(value),
+        };
+
+        let entry = &self.entries[entry];
+        if let Some(field) = entry.fields.iter().find(|field| field.key == key) {
+            self.tokens[field.value] = value_token;
+        } else {
+            // Follow the layout of the entry: one field per line if it already spans
+            // multiple lines, otherwise keep everything on the same line.
+            // New fields go after the last one, before any trailing annotations.
+            let end = match entry.fields.last() {
+                Some(field) => field.value + 1,
+                None => entry.header_end,
+            };
+            let separator = self.tokens[entry.start..end]
+                .iter()
+                .rev()
+                .find(|token| token.kind == Kind::Space && token.raw.contains('\n'))
+                .map(|token| token.raw[token.raw.rfind('\n').unwrap()..].to_string())
+                .unwrap_or_else(|| " ".to_string());
+
+            self.tokens.splice(
+                end..end,
+                [space(&separator), keyword(key), space(" "), value_token],
+            );
+        }
+        self.reindex();
+    }
+
+    /// Add a new `machine` entry with the given fields.
+    ///
+    /// The entry is added before the `default` entry if there is one, since `default`
+    /// must come last, and at the end of the file otherwise.
+    pub fn add_machine(&mut self, name: &str, fields: &[(&str, &str)]) {
+        let mut new = vec![keyword("machine"), space(" "), value(name)];
+        for (key, val) in fields {
+            new.extend([space(" "), keyword(key), space(" "), value(val)]);
+        }
+        new.push(space("\n"));
+
+        match self.entries.iter().find(|entry| entry.name.is_none()) {
+            Some(default) => {
+                let start = default.start;
+                self.tokens.splice(start..start, new);
+            }
+            None => {
+                // A macro body runs until a blank line, so terminate it first.
+                let in_macro = self.in_macro();
+                if !self.tokens.is_empty() && !self.to_string().ends_with('\n') {
+                    self.tokens.push(space("\n"));
+                }
+                if in_macro {
+                    self.tokens.push(space("\n"));
+                }
+                self.tokens.extend(new);
+            }
+        }
+        self.reindex();
+    }
+
+    /// Whether the file ends inside a `macdef` body.
+    fn in_macro(&self) -> bool {
+        // The name of the macro may or may not be followed by its body.
+        let mut kinds = self
+            .tokens
+            .iter()
+            .rev()
+            .map(|token| &token.kind)
+            .filter(|kind| **kind != Kind::Space);
+        match (kinds.next(), kinds.next()) {
+            (Some(Kind::Macro), _) => true,
+            (Some(Kind::Value(_)), Some(Kind::Keyword(keyword))) => keyword == "macdef",
+            _ => false,
+        }
+    }
+
+    /// Remove an entry.
+    pub fn remove(&mut self, entry: usize) {
+        let entry = &self.entries[entry];
+        self.remove_tokens(entry.start, entry.end);
+    }
+
+    /// Remove `key` from the given entry. Returns `false` if the entry doesn't have it.
+    pub fn remove_field(&mut self, entry: usize, key: &str) -> bool {
+        match self.entries[entry]
+            .fields
+            .iter()
+            .find(|field| field.key == key)
+        {
+            Some(field) => {
+                self.remove_tokens(field.start, field.value + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the tokens in `start..end` along with the whitespace used to lay them out.
+    fn remove_tokens(&mut self, start: usize, end: usize) {
+        let at_line_start = start == 0 || {
+            let before = &self.tokens[start - 1];
+            before.kind == Kind::Space && before.raw.contains('\n')
+        };
+
+        if at_line_start {
+            // Remove the indentation before the tokens and the rest of their last line.
+            let mut before_blank = start == 0;
+            if start > 0 {
+                let before = &mut self.tokens[start - 1].raw;
+                before.truncate(before.rfind('\n').unwrap() + 1);
+                before_blank = before.ends_with("\n\n");
+            }
+            if let Some(after) = self.tokens.get_mut(end).filter(|t| t.kind == Kind::Space) {
+                match after.raw.find('\n') {
+                    Some(i) => {
+                        after.raw.drain(..=i);
+                        // Don't leave two blank lines where the tokens were.
+                        if before_blank {
+                            if let Some(i) = after.raw.find('\n') {
+                                if after.raw[..i].trim().is_empty() {
+                                    after.raw.drain(..=i);
+                                }
+                            }
+                        }
+                    }
+                    None => after.raw.clear(),
+                }
+            }
+        } else if self.tokens[start - 1].kind == Kind::Space {
+            self.tokens[start - 1].raw.clear();
+        }
+
+        self.tokens.drain(start..end);
+        self.reindex();
+    }
+
+    /// Rebuild the entry index from the tokens.
+    fn reindex(&mut self) {
+        // Merge adjacent whitespace and drop empty tokens left behind by edits.
+        let mut tokens: Vec<Token> = Vec::with_capacity(self.tokens.len());
+        for token in self.tokens.drain(..) {
+            if token.raw.is_empty() && token.kind == Kind::Space {
+                continue;
+            }
+            match tokens.last_mut() {
+                Some(last) if last.kind == Kind::Space && token.kind == Kind::Space => {
+                    last.raw.push_str(&token.raw);
+                }
+                _ => tokens.push(token),
+            }
+        }
+        self.tokens = tokens;
+
+        self.entries.clear();
+        let mut key: Option<(usize, &str)> = None;
+        let mut in_entry = false;
+        for (i, token) in self.tokens.iter().enumerate() {
+            match &token.kind {
+                Kind::Keyword(keyword) if keyword == "default" => {
+                    self.entries.push(Entry {
+                        name: None,
+                        start: i,
+                        header_end: i + 1,
+                        end: i + 1,
+                        fields: Vec::new(),
+                        annotations: Vec::new(),
+                    });
+                    key = None;
+                    in_entry = true;
+                }
+                Kind::Keyword(keyword) => {
+                    key = Some((i, keyword));
+                    in_entry = in_entry && keyword != "macdef";
+                }
+                Kind::Value(value) => match key.take() {
+                    Some((start, "machine")) => {
+                        self.entries.push(Entry {
+                            name: Some(value.clone()),
+                            start,
+                            header_end: i + 1,
+                            end: i + 1,
+                            fields: Vec::new(),
+                            annotations: Vec::new(),
+                        });
+                        in_entry = true;
+                    }
+                    Some((_, "macdef")) | None => {}
+                    Some((start, other)) => {
+                        if let Some(entry) = self.entries.last_mut() {
+                            entry.end = i + 1;
+                            entry.fields.push(Field {
+                                key: normalize(other).to_string(),
+                                start,
+                                value: i,
+                            });
+                        }
+                    }
+                },
+                Kind::Comment if in_entry && parse_annotation(&token.raw).is_some() => {
+                    if let Some(entry) = self.entries.last_mut() {
+                        entry.end = i + 1;
+                        entry.annotations.push(i);
+                    }
+                }
+                Kind::Space | Kind::Comment | Kind::Macro => {}
+            }
+        }
+    }
+}
+
+/// Parse a `#cargo-<name> <value>` comment into its name and value.
+fn parse_annotation(comment: &str) -> Option<(&str, &str)> {
+    let annotation = comment
+        .strip_prefix('#')?
+        .trim_start()
+        .strip_prefix("cargo-")?;
+    let (name, value) = annotation
+        .split_once(char::is_whitespace)
+        .unwrap_or((annotation, ""));
+    Some((name, value.trim()))
+}
+
+impl fmt::Display for Netrc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in &self.tokens {
+            f.write_str(&token.raw)?;
+        }
+        Ok(())
+    }
+}
+
+fn normalize(key: &str) -> &str {
+    match key {
+        "user" => "login",
+        key => key,
+    }
+}
+
+fn space(raw: &str) -> Token {
+    Token {
+        kind: Kind::Space,
+        raw: raw.to_string(),
+    }
+}
+
+fn keyword(raw: &str) -> Token {
+    Token {
+        kind: Kind::Keyword(raw.to_string()),
+        raw: raw.to_string(),
+    }
+}
+
+fn value(value: &str) -> Token {
+    Token {
+        kind: Kind::Value(value.to_string()),
+        raw: quote(value),
+    }
+}
+
+/// Quote a value if it can't be written as a bare word.
+fn quote(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value.starts_with('#')
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\');
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Lexer {
+            src,
+            pos: 0,
+            line: 1,
+            tokens: Vec::new(),
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut in_entry = false;
+        loop {
+            self.space();
+            if self.rest().is_empty() {
+                break;
+            }
+            if self.rest().starts_with('#') {
+                let len = self.rest().find('\n').unwrap_or(self.rest().len());
+                self.push(Kind::Comment, len);
+                continue;
+            }
+
+            let (keyword, len) = self.word()?;
+            self.push(Kind::Keyword(keyword.clone()), len);
+            match keyword.as_str() {
+                "machine" => {
+                    self.value(&keyword)?;
+                    in_entry = true;
+                }
+                "default" => in_entry = true,
+                "macdef" => {
+                    self.value(&keyword)?;
+                    self.macro_body();
+                    in_entry = false;
+                }
+                // Besides `login`, `user`, `account` and `password`, this accepts custom fields.
+                _ if in_entry => self.value(&keyword)?,
+                _ => return Err(self.error(format!("bad toplevel token '{keyword}'"))),
+            }
+        }
+        Ok(self.tokens)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn push(&mut self, kind: Kind, len: usize) {
+        let raw = &self.src[self.pos..self.pos + len];
+        self.line += raw.matches('\n').count();
+        self.pos += len;
+        self.tokens.push(Token {
+            kind,
+            raw: raw.to_string(),
+        });
+    }
+
+    fn space(&mut self) {
+        let len = self
+            .rest()
+            .find(|c: char| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+            .unwrap_or(self.rest().len());
+        if len > 0 {
+            self.push(Kind::Space, len);
+        }
+    }
+
+    /// Read a bare or quoted word, returning its unescaped value and raw length.
+    fn word(&self) -> Result<(String, usize), ParseError> {
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        let quoted = self.rest().starts_with('"');
+        if quoted {
+            chars.next();
+        }
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' if quoted => return Ok((value, i + 1)),
+                ' ' | '\t' | '\r' | '\n' if !quoted => return Ok((value, i)),
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(escaped);
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+        if quoted {
+            return Err(self.error("unterminated quoted string".to_string()));
+        }
+        Ok((value, self.rest().len()))
+    }
+
+    /// Read the value following `keyword`.
+    fn value(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.space();
+        if self.rest().is_empty() {
+            return Err(self.error(format!("missing value for '{keyword}'")));
+        }
+        let (value, len) = self.word()?;
+        self.push(Kind::Value(value), len);
+        Ok(())
+    }
+
+    /// Read the body of a `macdef`, which ends at the first blank line.
+    fn macro_body(&mut self) {
+        let mut len = match self.rest().find('\n') {
+            Some(i) => i + 1,
+            None => self.rest().len(),
+        };
+        while len < self.rest().len() {
+            let line = &self.rest()[len..];
+            let line = &line[..line.find('\n').map_or(line.len(), |i| i + 1)];
+            if line.trim().is_empty() {
+                break;
+            }
+            len += line.len();
+        }
+        if len > 0 {
+            self.push(Kind::Macro, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small deterministic random number generator (xorshift), so that the property
+    /// tests don't need a dependency and always check the same cases.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+            choices[self.next() as usize % choices.len()]
+        }
+    }
+
+    const SPACES: [&str; 6] = [" ", "  ", "\t", "\n", "\r\n", "\n\n  "];
+    const VALUES: [&str; 8] = [
+        "user",
+        "p@ss:word",
+        "\"with space\"",
+        "\"quote \\\" inside\"",
+        "back\\\\slash",
+        "\"\"",
+        "#not-a-comment",
+        "ünïcode",
+    ];
+
+    /// Generate a random, valid .netrc file.
+    fn generate(rng: &mut Rng) -> String {
+        let mut src = String::new();
+        let entries = rng.next() % 5;
+        for i in 0..entries {
+            match rng.next() % 6 {
+                0 => src.push_str("# a comment"),
+                1 => src.push_str("macdef init\ncd /pub\nbinary\n"),
+                _ => {
+                    src.push_str("machine ");
+                    src.push_str(rng.pick(&["example.com", "\"quoted.com\"", "host:8080"]));
+                    for _ in 0..rng.next() % 4 {
+                        src.push_str(rng.pick(&SPACES));
+                        src.push_str(rng.pick(&["login", "user", "account", "password", "tenant"]));
+                        src.push_str(rng.pick(&SPACES));
+                        src.push_str(rng.pick(&VALUES));
+                    }
+                    if rng.next().is_multiple_of(3) {
+                        src.push_str("\n  #cargo-path /index");
+                    }
+                }
+            }
+            if i + 1 < entries || rng.next().is_multiple_of(2) {
+                src.push_str(rng.pick(&["\n", "\r\n", "\n\n"]));
+            }
+        }
+        src
+    }
+
+    #[test]
+    fn parse_and_serialize_round_trip() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let src = generate(&mut rng);
+            let netrc = Netrc::parse(&src).unwrap_or_else(|e| panic!("{e} in {src:?}"));
+            assert_eq!(netrc.to_string(), src);
+        }
+    }
+
+    #[test]
+    fn set_values_survive_serialization() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let mut src = generate(&mut rng);
+            src.push_str("\nmachine target.com login old\n");
+            let mut netrc = Netrc::parse(&src).unwrap();
+            let (entry, _) = netrc
+                .entries()
+                .find(|(_, name)| *name == Some("target.com"))
+                .unwrap();
+            let value = rng.pick(&["new", "with space", "quote\"", "back\\slash", "", "#hash"]);
+            netrc.set(entry, "password", value);
+
+            let written = Netrc::parse(&netrc.to_string()).unwrap();
+            assert_eq!(written.get(entry, "login"), Some("old"));
+            assert_eq!(written.get(entry, "password"), Some(value));
+            // Only the target entry changed.
+            assert!(netrc
+                .to_string()
+                .starts_with(&src[..src.len() - "login old\n".len()]));
+        }
+    }
+
+    #[test]
+    fn round_trip_examples() {
+        for src in [
+            "",
+            "\n",
+            "machine example.com login user password pass",
+            "machine example.com login user password pass\n",
+            "machine example.com\r\n  login user\r\n  password pass\r\n",
+            "# comment\nmachine example.com # trailing\n  login user\n",
+            "machine example.com login \"a \\\"quoted\\\" value\" password a\\ b\n",
+            "macdef init\ncd /pub\n\nmachine example.com login user\n",
+            "macdef init\ncd /pub",
+            "machine a.com login a\ndefault login anonymous password me@\n",
+            "\t machine   a.com\tlogin\ta  \n\n\n",
+        ] {
+            assert_eq!(Netrc::parse(src).unwrap().to_string(), src);
+        }
+    }
+
+    #[test]
+    fn parse_values() {
+        let netrc = Netrc::parse(
+            "machine example.com user u password \"a b\\\"c\" tenant acme\n\
+             macdef init\nmachine ignored.com\n\n\
+             default password d\n",
+        )
+        .unwrap();
+        let entries: Vec<_> = netrc.entries().collect();
+        assert_eq!(entries, [(0, Some("example.com")), (1, None)]);
+        assert_eq!(netrc.get(0, "login"), Some("u"));
+        assert_eq!(netrc.get(0, "password"), Some("a b\"c"));
+        assert_eq!(netrc.get(0, "tenant"), Some("acme"));
+        assert_eq!(netrc.get(0, "account"), None);
+        assert_eq!(netrc.get(1, "password"), Some("d"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for src in ["machine", "machine a.com login \"open", "login user"] {
+            assert!(Netrc::parse(src).is_err(), "{src:?}");
+        }
+    }
+
+    #[test]
+    fn set_replaces_a_value() {
+        let mut netrc = Netrc::parse("# c\nmachine a.com login old # keep\n").unwrap();
+        netrc.set(0, "login", "new value");
+        assert_eq!(
+            netrc.to_string(),
+            "# c\nmachine a.com login \"new value\" # keep\n"
+        );
+    }
+
+    #[test]
+    fn set_adds_a_field_on_the_same_line() {
+        let mut netrc = Netrc::parse("machine a.com login user\n").unwrap();
+        netrc.set(0, "password", "pass");
+        assert_eq!(
+            netrc.to_string(),
+            "machine a.com login user password pass\n"
+        );
+    }
+
+    #[test]
+    fn set_adds_a_field_on_its_own_line() {
+        let mut netrc = Netrc::parse("machine a.com\n  login user\n#cargo-path /x\n").unwrap();
+        netrc.set(0, "password", "pass");
+        assert_eq!(
+            netrc.to_string(),
+            "machine a.com\n  login user\n  password pass\n#cargo-path /x\n"
+        );
+    }
+
+    #[test]
+    fn add_machine_at_the_end() {
+        let mut netrc = Netrc::parse("# c\nmachine a.com login a").unwrap();
+        netrc.add_machine("b.com", &[("login", "b"), ("password", "p w")]);
+        assert_eq!(
+            netrc.to_string(),
+            "# c\nmachine a.com login a\nmachine b.com login b password \"p w\"\n"
+        );
+    }
+
+    #[test]
+    fn add_machine_before_default() {
+        let mut netrc = Netrc::parse("machine a.com login a\ndefault login d\n").unwrap();
+        netrc.add_machine("b.com", &[("login", "b")]);
+        assert_eq!(
+            netrc.to_string(),
+            "machine a.com login a\nmachine b.com login b\ndefault login d\n"
+        );
+    }
+
+    #[test]
+    fn add_machine_after_macro() {
+        let mut netrc = Netrc::parse("macdef init\ncd /pub\n").unwrap();
+        netrc.add_machine("a.com", &[("login", "a")]);
+        assert_eq!(
+            netrc.to_string(),
+            "macdef init\ncd /pub\n\nmachine a.com login a\n"
+        );
+        assert_eq!(netrc.get(0, "login"), Some("a"));
+    }
+
+    #[test]
+    fn remove_an_entry() {
+        let mut netrc = Netrc::parse(
+            "# first\nmachine a.com\n  login a\n\nmachine b.com login b\n\nmacdef m\nx\n",
+        )
+        .unwrap();
+        netrc.remove(0);
+        // The blank line that separated the entries is kept, but not doubled.
+        assert_eq!(
+            netrc.to_string(),
+            "# first\n\nmachine b.com login b\n\nmacdef m\nx\n"
+        );
+        netrc.remove(0);
+        assert_eq!(netrc.to_string(), "# first\n\nmacdef m\nx\n");
+    }
+
+    #[test]
+    fn remove_an_entry_on_a_shared_line() {
+        let mut netrc = Netrc::parse("machine a.com login a machine b.com login b\n").unwrap();
+        netrc.remove(1);
+        assert_eq!(netrc.to_string(), "machine a.com login a\n");
+    }
+
+    #[test]
+    fn remove_a_field() {
+        let mut netrc = Netrc::parse(
+            "machine a.com login a password p\nmachine b.com\n  login b\n  password p\n",
+        )
+        .unwrap();
+        assert!(netrc.remove_field(0, "password"));
+        assert!(netrc.remove_field(1, "password"));
+        assert!(!netrc.remove_field(1, "password"));
+        assert_eq!(
+            netrc.to_string(),
+            "machine a.com login a\nmachine b.com\n  login b\n"
+        );
+    }
+
+    #[test]
+    fn set_field_after_name_without_whitespace() {
+        let mut netrc = Netrc::parse("\"machine\"a.com").unwrap();
+        netrc.set(0, "login", "user");
+        assert_eq!(netrc.to_string(), "\"machine\"a.com login user");
+    }
+
+    #[test]
+    fn add_machine_after_unterminated_macro_name() {
+        let mut netrc = Netrc::parse("\"macdef\"\"init\"").unwrap();
+        netrc.add_machine("a.com", &[("login", "user")]);
+        assert_eq!(
+            netrc.to_string(),
+            "\"macdef\"\"init\"\n\nmachine a.com login user\n"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Built-in token formats for common registries.
+
+/// A named token format.
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub format: &'static str,
+    /// The .netrc fields used by the format.
+    pub fields: &'static [&'static str],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "plain",
+        description: "The password as is, e.g. for crates.io-compatible registries",
+        format: "{{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "bearer",
+        description: "The password as a bearer token",
+        format: "Bearer {{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "basic",
+        description: "HTTP basic authentication with the login and password",
+        format: "Basic {{base64 (concat login ':' password)}}",
+        fields: &["login", "password"],
+    },
+    Preset {
+        name: "artifactory",
+        description: "JFrog Artifactory, with an access token as the password",
+        format: "Bearer {{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "cloudsmith",
+        description: "Cloudsmith, with your username and an API key, like `basic`",
+        format: "Basic {{base64 (concat login ':' password)}}",
+        fields: &["login", "password"],
+    },
+    Preset {
+        name: "codeartifact",
+        description: "AWS CodeArtifact, with an authorization token as the password",
+        format: "Bearer {{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "gitea",
+        description: "Gitea and Forgejo, with an access token as the password",
+        format: "Bearer {{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "gitlab",
+        description: "GitLab, with a deploy or access token and its username, like `basic`",
+        format: "Basic {{base64 (concat login ':' password)}}",
+        fields: &["login", "password"],
+    },
+    Preset {
+        name: "kellnr",
+        description: "Kellnr, with an authentication token as the password",
+        format: "{{password}}",
+        fields: &["password"],
+    },
+    Preset {
+        name: "shipyard",
+        description: "Shipyard.rs, with an auth token as the password",
+        format: "{{password}}",
+        fields: &["password"],
+    },
+];
+
+/// Find a preset by name.
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// Print the presets along with their format and the .netrc fields they use.
+pub fn print() {
+    let width = PRESETS
+        .iter()
+        .map(|preset| preset.name.len())
+        .max()
+        .unwrap_or(0);
+    for preset in PRESETS {
+        println!("{:width$}  {}", preset.name, preset.description);
+        println!("{:width$}  format: {}", "", preset.format);
+        println!("{:width$}  fields: {}", "", preset.fields.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cargo_credential::Secret;
+    use handlebars::RenderErrorReason;
+    use zeroize::Zeroizing;
+
+    use super::*;
+    use crate::format::{Data, Format};
+
+    /// Every preset compiles, and uses exactly the .netrc fields it lists.
+    #[test]
+    fn presets() {
+        for preset in PRESETS {
+            let format = Format::new(preset.format).unwrap();
+            let data = |fields: &[&'static str]| {
+                let mut data = Data::default();
+                for &field in fields {
+                    data.variables
+                        .insert(field, Secret::from(Zeroizing::new(field.to_string())));
+                }
+                data
+            };
+            assert!(
+                format.render(&data(preset.fields)).is_ok(),
+                "{}",
+                preset.name
+            );
+            for field in preset.fields {
+                let fields: Vec<_> = preset
+                    .fields
+                    .iter()
+                    .copied()
+                    .filter(|f| f != field)
+                    .collect();
+                match format.render(&data(&fields)).unwrap_err().reason() {
+                    RenderErrorReason::MissingVariable(Some(name)) => {
+                        assert_eq!(name, field, "{}", preset.name)
+                    }
+                    reason => panic!("unexpected error for {}: {reason:?}", preset.name),
+                }
+            }
+        }
+    }
+}